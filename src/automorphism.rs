@@ -0,0 +1,247 @@
+use crate::{
+    matching::Matching,
+    output_set::{CVec, OutputSet},
+    value::Value,
+};
+
+/// Returns generators of the group of channel permutations that map an output set to itself.
+///
+/// The permutations use the same convention as [`OutputSet::permute_channels`]. Processing the
+/// channels from last to first, this searches for automorphisms that fix all smaller channels and
+/// move the current channel to each point not yet in its orbit. The generators found this way
+/// form a strong generating set for the stabilizer chain of the channel order.
+pub fn automorphism_generators<V: Value>(output_set: &OutputSet<V>) -> Vec<CVec<usize>> {
+    let channels = output_set.channels();
+    let abstraction = output_set.abstraction();
+
+    let mut compatible = Matching::new(channels);
+    let incomplete =
+        compatible.filter(|a, b| abstraction.channel_values(a) == abstraction.channel_values(b));
+    assert!(!incomplete);
+
+    let mut generators: Vec<CVec<usize>> = vec![];
+
+    for channel in (0..channels).rev() {
+        for target in channel + 1..channels {
+            if orbit(&generators, channel).contains(&target) {
+                continue;
+            }
+
+            let mut matching = compatible.clone();
+
+            let incomplete = (0..channel).any(|fixed| matching.select(fixed, fixed));
+
+            if incomplete || matching.select(channel, target) {
+                continue;
+            }
+
+            if let Some(perm) = search(output_set, matching) {
+                generators.push(perm);
+            }
+        }
+    }
+
+    generators
+}
+
+/// Returns the orbit of a channel under the group generated by the given permutations.
+pub fn orbit(generators: &[CVec<usize>], channel: usize) -> CVec<usize> {
+    let mut orbit = CVec::new();
+    orbit.push(channel);
+
+    let mut pos = 0;
+
+    while pos < orbit.len() {
+        let point = orbit[pos];
+        pos += 1;
+        for perm in generators.iter() {
+            let image = perm[point];
+            if !orbit.contains(&image) {
+                orbit.push(image);
+            }
+        }
+    }
+
+    orbit
+}
+
+/// Returns one comparator `(i, j)` with `i < j` for each class of comparators that produce
+/// equivalent output sets when applied to an output set with the given automorphisms.
+pub fn comparator_representatives(
+    generators: &[CVec<usize>],
+    channels: usize,
+) -> Vec<(usize, usize)> {
+    let index = |i: usize, j: usize| j * channels + i;
+
+    let mut parent = (0..channels * channels).collect::<Vec<_>>();
+
+    fn find(parent: &mut [usize], mut node: usize) -> usize {
+        while parent[node] != node {
+            parent[node] = parent[parent[node]];
+            node = parent[node];
+        }
+        node
+    }
+
+    for perm in generators.iter() {
+        for j in 0..channels {
+            for i in 0..j {
+                let (image_i, image_j) = (perm[i], perm[j]);
+                if image_i < image_j {
+                    let root = find(&mut parent, index(i, j));
+                    let image_root = find(&mut parent, index(image_i, image_j));
+                    parent[root.max(image_root)] = root.min(image_root);
+                }
+            }
+        }
+    }
+
+    let mut representatives = vec![];
+
+    for j in 0..channels {
+        for i in 0..j {
+            if find(&mut parent, index(i, j)) == index(i, j) {
+                representatives.push((i, j));
+            }
+        }
+    }
+
+    representatives
+}
+
+fn search<V: Value>(output_set: &OutputSet<V>, matching: Matching) -> Option<CVec<usize>> {
+    let channels = output_set.channels();
+
+    let branch = (0..channels)
+        .map(|a| (matching.matches_a(a).count_ones(), a))
+        .filter(|&(count, _)| count > 1)
+        .min();
+
+    if let Some((_, channel_a)) = branch {
+        for channel_b in 0..channels {
+            if !matching.contains(channel_a, channel_b) {
+                continue;
+            }
+            let mut next_matching = matching.clone();
+            if !next_matching.select(channel_a, channel_b) {
+                if let Some(perm) = search(output_set, next_matching) {
+                    return Some(perm);
+                }
+            }
+        }
+        None
+    } else {
+        let perm = (0..channels)
+            .map(|a| matching.unique_match_a(a))
+            .collect::<Option<CVec<_>>>()?;
+
+        let mut permuted = output_set.clone();
+        permuted.permute_channels(perm.clone());
+
+        if permuted == *output_set {
+            Some(perm)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn group_order(generators: &[CVec<usize>], channels: usize) -> usize {
+        let mut elements = vec![(0..channels).collect::<CVec<usize>>()];
+        let mut pos = 0;
+        while pos < elements.len() {
+            let element = elements[pos].clone();
+            pos += 1;
+            for perm in generators {
+                let product = (0..channels).map(|i| perm[element[i]]).collect::<CVec<_>>();
+                if !elements.contains(&product) {
+                    elements.push(product);
+                }
+            }
+        }
+        elements.len()
+    }
+
+    #[test]
+    fn automorphism_group_order() {
+        let channels = 5;
+
+        let all_values: OutputSet = OutputSet::all_values(channels);
+
+        let mut perms: Vec<CVec<usize>> = vec![(0..channels).collect()];
+        for i in 1..channels {
+            perms = perms
+                .into_iter()
+                .flat_map(|perm| {
+                    (0..=i).map(move |j| {
+                        let mut perm = perm.clone();
+                        perm.swap(i, j);
+                        perm
+                    })
+                })
+                .collect();
+        }
+
+        for j in 0..channels {
+            for i in 0..j {
+                let tmp = all_values.apply_comparator(i, j);
+                for j_2 in 0..channels {
+                    for i_2 in 0..j_2 {
+                        let output_set = tmp.apply_comparator(i_2, j_2);
+
+                        let generators = automorphism_generators(&output_set);
+
+                        for perm in generators.iter() {
+                            let mut permuted = output_set.clone();
+                            permuted.permute_channels(perm.clone());
+                            assert_eq!(permuted, output_set);
+                        }
+
+                        let expected = perms
+                            .iter()
+                            .filter(|perm| {
+                                let mut permuted = output_set.clone();
+                                permuted.permute_channels((*perm).clone());
+                                permuted == output_set
+                            })
+                            .count();
+
+                        assert_eq!(group_order(&generators, channels), expected);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(
+            group_order(&automorphism_generators(&all_values), channels),
+            120
+        );
+    }
+
+    #[test]
+    fn equivalent_comparators() {
+        let channels = 6;
+
+        let output_set: OutputSet = OutputSet::all_values(channels).apply_comparator(0, 1);
+
+        let generators = automorphism_generators(&output_set);
+        let representatives = comparator_representatives(&generators, channels);
+
+        for j in 0..channels {
+            for i in 0..j {
+                let (canonical, _) = output_set.apply_comparator(i, j).canonical_form();
+                assert!(representatives.iter().any(|&(a, b)| output_set
+                    .apply_comparator(a, b)
+                    .canonical_form()
+                    .0
+                    == canonical));
+            }
+        }
+
+        assert!(representatives.len() < channels * (channels - 1) / 2);
+    }
+}
@@ -0,0 +1,203 @@
+//! Checks a certificate written by `sortnetopt --certificate`.
+//!
+//! This only relies on applying comparators, permutations and reflections to output sets and on
+//! testing output sets for subsets, not on the subsumption index, so it can be used to recheck a
+//! lower bound independently.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+use structopt::StructOpt;
+
+use sortnetopt::{
+    certificate::{comparators, CertificateReader},
+    input_set::InputSpec,
+    layer_file::read_layer,
+    logging,
+    output_set::OutputSet,
+    target::{Target, TargetSpec},
+    value::Value,
+};
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Certificate to check
+    #[structopt(parse(from_os_str))]
+    certificate: PathBuf,
+    /// Inputs the network has to handle: `all`, `blocks:<a>,<b>,...`, `merge:<m>,<n>`,
+    /// `inversions:<t>` or `file:<path>`
+    #[structopt(short = "i", long = "input", default_value = "all")]
+    input: InputSpec,
+    /// Target the certificate shows a lower bound for: `sort`, `median`, `top:<k>`, `kth:<k>` or
+    /// `select:<k>`
+    #[structopt(short = "t", long = "target", default_value = "sort")]
+    target: TargetSpec,
+    /// Also check that the layer files `layer-<index>.bin` in this directory, as written by
+    /// `--save-layers`, agree with the certificate
+    #[structopt(long = "layers", parse(from_os_str))]
+    layers: Option<PathBuf>,
+}
+
+fn main() {
+    logging::setup();
+
+    let opt = Opt::from_args();
+
+    let channels = match open(&opt.certificate).and_then(CertificateReader::<_, u32>::new) {
+        Ok(reader) => reader.channels(),
+        Err(err) => fail(&opt.certificate, err),
+    };
+
+    if !opt.target.is_valid(channels) {
+        log::error!("target {} is not valid for width {}", opt.target, channels);
+        std::process::exit(1);
+    }
+
+    if channels <= u16::MAX_CHANNELS {
+        check::<u16>(&opt, channels);
+    } else {
+        check::<u32>(&opt, channels);
+    }
+}
+
+fn open(path: &Path) -> io::Result<BufReader<File>> {
+    File::open(path).map(BufReader::new)
+}
+
+fn fail(path: &Path, err: io::Error) -> ! {
+    log::error!("cannot read {}: {}", path.display(), err);
+    std::process::exit(1);
+}
+
+fn reject(layer: usize, message: &str) -> ! {
+    log::error!("layer {}: {}", layer, message);
+    std::process::exit(1);
+}
+
+fn check<V: Value>(opt: &Opt, channels: usize) {
+    let input = match opt.input.output_set::<V>(channels) {
+        Ok(input) => input,
+        Err(err) => {
+            log::error!("cannot build input set {}: {}", opt.input, err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut reader = match open(&opt.certificate).and_then(CertificateReader::<_, V>::new) {
+        Ok(reader) => reader,
+        Err(err) => fail(&opt.certificate, err),
+    };
+
+    let pairs = comparators(channels);
+    let reflection_symmetric = opt.target.is_reflection_symmetric(channels);
+
+    let mut parents: Vec<OutputSet<V>> = vec![];
+    let mut checked = 0;
+    let mut reached = false;
+
+    loop {
+        let layer = match reader.next_layer() {
+            Ok(Some(layer)) => layer,
+            Ok(None) => break,
+            Err(err) => fail(&opt.certificate, err),
+        };
+        let index = layer.layer;
+
+        if index == 0 {
+            if !layer
+                .output_sets
+                .iter()
+                .any(|output_set| output_set.subsumes(&input))
+            {
+                reject(index, "no output set is a subset of the input set");
+            }
+        } else {
+            let reflected = layer.witnesses.iter().any(|witness| {
+                witness
+                    .as_ref()
+                    .is_some_and(|witness| witness.transform.reflected)
+            });
+            if reflected && !reflection_symmetric {
+                reject(
+                    index,
+                    &format!(
+                        "witnesses use reflection, but target {} is not symmetric under reflection",
+                        opt.target
+                    ),
+                );
+            }
+
+            let invalid = layer
+                .witnesses
+                .par_iter()
+                .enumerate()
+                .find_any(|(position, witness)| {
+                    let parent = &parents[position / pairs.len()];
+                    let (i, j) = pairs[position % pairs.len()];
+                    match witness {
+                        None => !parent.implications().is_associated(i, j),
+                        Some(witness) => {
+                            let generated = witness.transform.apply(&parent.apply_comparator(i, j));
+                            !layer.output_sets[witness.survivor].subsumes(&generated)
+                        }
+                    }
+                });
+
+            if let Some((position, _)) = invalid {
+                let (i, j) = pairs[position % pairs.len()];
+                reject(
+                    index,
+                    &format!(
+                        "invalid witness for comparator ({}, {}) applied to output set {}",
+                        i,
+                        j,
+                        position / pairs.len()
+                    ),
+                );
+            }
+        }
+
+        if let Some(dir) = &opt.layers {
+            let path = dir.join(format!("layer-{}.bin", index));
+            match open(&path).and_then(read_layer::<_, V>) {
+                Ok((_, output_sets)) => {
+                    if output_sets != layer.output_sets {
+                        reject(index, "layer file does not agree with the certificate");
+                    }
+                }
+                Err(err) => fail(&path, err),
+            }
+        }
+
+        log::info!("layer {} size: {} checked", index, layer.output_sets.len());
+        checked += 1;
+
+        if layer
+            .output_sets
+            .par_iter()
+            .any(|output_set| opt.target.is_satisfied(output_set))
+        {
+            log::info!("layer {} reaches target {}", index, opt.target);
+            reached = true;
+            break;
+        }
+
+        parents = layer.output_sets;
+    }
+
+    if checked == 0 {
+        log::error!("certificate contains no layers");
+        std::process::exit(1);
+    }
+
+    let bound = if reached { checked - 1 } else { checked };
+    log::info!(
+        "no network with less than {} comparators reaches target {}",
+        bound,
+        opt.target
+    );
+}
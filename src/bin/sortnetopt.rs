@@ -1,20 +1,147 @@
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+use std::{
+    collections::{hash_map::RandomState, HashSet},
+    fs::File,
+    hash::BuildHasher,
+    io::BufWriter,
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
 use indicatif::{ProgressBar, ProgressStyle};
+use parking_lot::Mutex;
 use rayon::prelude::*;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    flag,
+};
 use structopt::StructOpt;
 
 use sortnetopt::{
+    automorphism::{automorphism_generators, comparator_representatives},
+    bitmap_output_set::BitmapOutputSet,
+    certificate::{collect_witnesses, witness_index, CertificateWriter, Transform, Witnesses},
+    checkpoint::Checkpoint,
+    distributed::{run_worker, Coordinator},
+    input_set::InputSpec,
+    layer_file::write_layer,
+    layer_store::{LayerStore, ProvenanceFiles, DEFAULT_SEGMENT_LEN},
     logging,
-    output_set::OutputSet,
-    subsume_index::{incremental_minimal_elements, AbstractedPair},
+    network::{maximal_layers, Network},
+    output_set::{CVec, OutputSet},
+    path_count::count_optimal_networks,
+    provenance::{reconstruct, Provenance},
+    subsume_index::{
+        resume_minimal_elements, resume_minimal_elements_in, stratified_minimal_elements,
+        AbstractedPair, IncrementalState, Symmetries,
+    },
+    target::{Target, TargetSpec},
+    topology::{Topology, TopologySpec},
+    value::Value,
 };
 
+/// Exit status after saving a checkpoint in response to SIGINT or SIGTERM.
+const EXIT_INTERRUPTED: i32 = 75;
+
+/// Number of independently locked shards of [`Seen`].
+const SEEN_SHARDS: usize = 256;
+
 #[derive(Debug, StructOpt)]
 struct Opt {
     /// Width (number of channels) of the sorting network
     width: usize,
+    /// Also prune output sets subsumed by a reflected output set
+    #[structopt(short = "r", long = "reflection")]
+    reflection: bool,
+    /// Bring generated output sets into canonical form and drop duplicates before pruning
+    #[structopt(short = "c", long = "canonical")]
+    canonical: bool,
+    /// Skip comparators that are equivalent to another comparator under an automorphism
+    #[structopt(short = "a", long = "automorphisms")]
+    automorphisms: bool,
+    /// Search for the minimal depth instead of the minimal size, adding a maximal layer of
+    /// parallel comparators in each step
+    #[structopt(short = "d", long = "depth")]
+    depth: bool,
+    /// Inputs the network has to handle: `all`, `blocks:<a>,<b>,...`, `merge:<m>,<n>`,
+    /// `inversions:<t>` or `file:<path>`
+    #[structopt(short = "i", long = "input", default_value = "all")]
+    input: InputSpec,
+    /// Stop at the first layer containing an output set that satisfies this target: `sort`,
+    /// `median`, `top:<k>`, `kth:<k>` or `select:<k>`
+    #[structopt(short = "t", long = "target", default_value = "sort")]
+    target: TargetSpec,
+    /// Only allow comparators between the channels connected in this topology: `complete`,
+    /// `path`, `mesh:<rows>x<cols>` or `edges:<a>-<b>,...`
+    #[structopt(long = "topology", default_value = "complete")]
+    topology: TopologySpec,
+    /// Count the networks of optimal size instead of finding one. This only merges equivalent
+    /// output sets and is feasible for small widths only
+    #[structopt(long = "count")]
+    count: bool,
+    /// Write each layer to `layer-<index>.bin` in this directory
+    #[structopt(long = "save-layers", parse(from_os_str))]
+    save_layers: Option<PathBuf>,
+    /// Save each finished layer to this directory, so the search can be resumed with `--resume`
+    #[structopt(long = "checkpoint", parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+    /// Continue from the newest layer saved in this checkpoint directory and keep saving layers
+    /// to it
+    #[structopt(long = "resume", parse(from_os_str))]
+    resume: Option<PathBuf>,
+    /// When saving checkpoints, also save the state within a layer after this many seconds
+    #[structopt(long = "checkpoint-interval", default_value = "600")]
+    checkpoint_interval: u64,
+    /// Write a certificate to this file that allows checking the lower bound independently
+    #[structopt(long = "certificate", parse(from_os_str))]
+    certificate: Option<PathBuf>,
+    /// Keep the inputs and the generated output sets waiting to be added to a layer, as well as the
+    /// provenance of the finished layers, in temporary files in this directory instead of memory
+    #[structopt(long = "layer-store", parse(from_os_str))]
+    layer_store: Option<PathBuf>,
+    /// Generate each layer completely and prune it in buckets of output sets with the same number
+    /// of values, from the smallest up
+    #[structopt(long = "stratified")]
+    stratified: bool,
+    /// Apply comparators to dense output sets using a bitmap with one bit for each possible value
+    #[structopt(long = "bitmap")]
+    bitmap: bool,
+    /// Listen on this address for workers started with `--worker` and the same options, and let
+    /// them generate the layers
+    #[structopt(long = "coordinator")]
+    coordinator: Option<String>,
+    /// Number of workers to wait for before starting the search with `--coordinator`
+    #[structopt(long = "workers", default_value = "1")]
+    workers: usize,
+    /// Connect to the coordinator at this address and generate shards of layers for it
+    #[structopt(long = "worker")]
+    worker: Option<String>,
+}
+
+impl Opt {
+    /// The options that determine the layers, which have to agree when resuming a search or
+    /// connecting a worker.
+    fn search_config(&self) -> String {
+        format!(
+            "width={} reflection={} canonical={} automorphisms={} depth={} input={} target={} \
+             topology={}",
+            self.width,
+            self.reflection,
+            self.canonical,
+            self.automorphisms,
+            self.depth,
+            self.input,
+            self.target,
+            self.topology
+        )
+    }
 }
 
 fn main() {
@@ -24,17 +151,462 @@ fn main() {
 
     let opt = Opt::from_args();
 
-    let output_set = OutputSet::all_values(opt.width);
+    if opt.depth && opt.automorphisms {
+        log::error!("--automorphisms cannot be combined with --depth");
+        std::process::exit(1);
+    }
+
+    if !opt.target.is_valid(opt.width) {
+        log::error!("target {} is not valid for width {}", opt.target, opt.width);
+        std::process::exit(1);
+    }
+
+    if opt.reflection && !opt.target.is_reflection_symmetric(opt.width) {
+        log::error!(
+            "target {} is not symmetric under reflection for width {}",
+            opt.target,
+            opt.width
+        );
+        std::process::exit(1);
+    }
+
+    if opt.count
+        && (opt.automorphisms
+            || opt.depth
+            || opt.save_layers.is_some()
+            || opt.layer_store.is_some())
+    {
+        log::error!(
+            "--count cannot be combined with --automorphisms, --depth, --save-layers or \
+             --layer-store"
+        );
+        std::process::exit(1);
+    }
+
+    if opt.certificate.is_some() && (opt.automorphisms || opt.depth || opt.count) {
+        log::error!("--certificate cannot be combined with --automorphisms, --depth or --count");
+        std::process::exit(1);
+    }
+
+    if opt.checkpoint.is_some() && opt.resume.is_some() {
+        log::error!("--checkpoint cannot be combined with --resume");
+        std::process::exit(1);
+    }
+
+    if (opt.checkpoint.is_some() || opt.resume.is_some())
+        && (opt.count || opt.certificate.is_some())
+    {
+        log::error!("--checkpoint and --resume cannot be combined with --count or --certificate");
+        std::process::exit(1);
+    }
+
+    if opt.coordinator.is_some() && opt.worker.is_some() {
+        log::error!("--coordinator cannot be combined with --worker");
+        std::process::exit(1);
+    }
+
+    if opt.coordinator.is_some()
+        && (opt.count || opt.certificate.is_some() || opt.layer_store.is_some())
+    {
+        log::error!(
+            "--coordinator cannot be combined with --count, --certificate or --layer-store"
+        );
+        std::process::exit(1);
+    }
+
+    if opt.coordinator.is_some() && opt.workers == 0 {
+        log::error!("--coordinator needs at least one worker");
+        std::process::exit(1);
+    }
+
+    if opt.worker.is_some()
+        && (opt.count
+            || opt.save_layers.is_some()
+            || opt.checkpoint.is_some()
+            || opt.resume.is_some()
+            || opt.certificate.is_some()
+            || opt.layer_store.is_some())
+    {
+        log::error!(
+            "--worker cannot be combined with --count, --save-layers, --checkpoint, --resume, \
+             --certificate or --layer-store"
+        );
+        std::process::exit(1);
+    }
+
+    if opt.stratified
+        && (opt.checkpoint.is_some()
+            || opt.resume.is_some()
+            || opt.layer_store.is_some()
+            || opt.coordinator.is_some()
+            || opt.worker.is_some())
+    {
+        log::error!(
+            "--stratified cannot be combined with --checkpoint, --resume, --layer-store, \
+             --coordinator or --worker"
+        );
+        std::process::exit(1);
+    }
+
+    let topology = match opt.topology.topology(opt.width) {
+        Ok(topology) => topology,
+        Err(err) => {
+            log::error!("cannot build topology {}: {}", opt.topology, err);
+            std::process::exit(1);
+        }
+    };
+
+    if !topology.is_complete() {
+        if opt.automorphisms || opt.canonical || opt.count || opt.certificate.is_some() {
+            log::error!(
+                "--automorphisms, --canonical, --count and --certificate require the complete \
+                 topology"
+            );
+            std::process::exit(1);
+        }
+
+        if opt.reflection && !topology.is_reversal_symmetric() {
+            log::error!(
+                "topology {} is not symmetric under reflection",
+                opt.topology
+            );
+            std::process::exit(1);
+        }
+    }
+
+    if opt.width <= u16::MAX_CHANNELS {
+        run::<u16>(&opt, &topology);
+    } else {
+        run::<u32>(&opt, &topology);
+    }
+}
+
+/// Sets `shutdown` on the first SIGINT or SIGTERM and exits immediately on the second one.
+fn install_shutdown_handler(shutdown: &Arc<AtomicBool>) {
+    for &signal in [SIGINT, SIGTERM].iter() {
+        // Registered before setting the flag, so this only exits if an earlier signal set it.
+        flag::register_conditional_shutdown(signal, 128 + signal, Arc::clone(shutdown)).unwrap();
+        flag::register(signal, Arc::clone(shutdown)).unwrap();
+    }
+}
+
+fn run<V: Value>(opt: &Opt, topology: &Topology) {
+    let restricted = !topology.is_complete();
+
+    let permutations = if restricted {
+        let automorphisms = topology.automorphisms().unwrap_or_else(|err| {
+            log::error!(
+                "cannot use topology {} for subsumption: {}",
+                opt.topology,
+                err
+            );
+            std::process::exit(1);
+        });
+        log::info!("topology automorphisms: {}", automorphisms.len());
+        Some(automorphisms)
+    } else {
+        None
+    };
+
+    let symmetries = Symmetries {
+        reflection: opt.reflection,
+        permutations,
+    };
+
+    let output_set = match opt.input.output_set::<V>(opt.width) {
+        Ok(output_set) => output_set,
+        Err(err) => {
+            log::error!("cannot build input set {}: {}", opt.input, err);
+            std::process::exit(1);
+        }
+    };
+
+    let reflection_symmetric = match &symmetries.permutations {
+        Some(permutations) => {
+            let reflected = output_set.reflect();
+            permutations.iter().any(|perm| {
+                let mut permuted = output_set.clone();
+                permuted.permute_channels(perm.clone());
+                permuted == reflected
+            })
+        }
+        None => output_set.reflect().canonical_form().0 == output_set.canonical_form().0,
+    };
+
+    if opt.reflection && !reflection_symmetric {
+        log::error!("input set {} is not symmetric under reflection", opt.input);
+        std::process::exit(1);
+    }
+
+    if let Some(addr) = &opt.worker {
+        work::<V>(opt, topology, &symmetries, addr);
+        return;
+    }
+
+    if opt.count {
+        let (size, count) = count_optimal_networks(
+            &output_set,
+            &opt.target,
+            opt.reflection,
+            |layer, classes| log::info!("layer {} classes: {}", layer, classes),
+        );
+        log::info!(
+            "{} networks of size {} reach target {}",
+            count,
+            size,
+            opt.target
+        );
+        return;
+    }
+
+    let input = output_set.clone();
+
+    let checkpoint = match (&opt.checkpoint, &opt.resume) {
+        (Some(dir), _) => Some(Checkpoint::create(dir, &opt.search_config())),
+        (_, Some(dir)) => Some(Checkpoint::open(dir, &opt.search_config())),
+        _ => None,
+    }
+    .map(|checkpoint| {
+        checkpoint.unwrap_or_else(|err| {
+            log::error!("cannot use checkpoint directory: {}", err);
+            std::process::exit(1);
+        })
+    });
+
+    let store = opt.layer_store.as_ref().map(|dir| {
+        LayerStore::new(dir, opt.width, DEFAULT_SEGMENT_LEN).unwrap_or_else(|err| {
+            log::error!("cannot use layer store directory: {}", err);
+            std::process::exit(1);
+        })
+    });
+
+    let mut coordinator = opt.coordinator.as_ref().map(|addr| {
+        let listener = TcpListener::bind(addr).unwrap_or_else(|err| {
+            log::error!("cannot listen on {}: {}", addr, err);
+            std::process::exit(1);
+        });
+        let mut coordinator = Coordinator::new(&opt.search_config(), opt.width);
+        while coordinator.workers() < opt.workers {
+            match coordinator.accept(&listener) {
+                Ok(worker) => log::info!(
+                    "worker {} connected ({}/{})",
+                    worker,
+                    coordinator.workers(),
+                    opt.workers
+                ),
+                Err(err) => log::warn!("rejected worker: {}", err),
+            }
+        }
+        coordinator
+    });
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    if checkpoint.is_some() {
+        install_shutdown_handler(&shutdown);
+    }
+
+    let mut layer = vec![AbstractedPair::new(
+        output_set,
+        (Provenance::default(), Witnesses::default()),
+    )];
 
-    let mut layer = vec![AbstractedPair::new(output_set, ())];
+    let mut history = match &store {
+        Some(store) => History::Files(store.provenance_files()),
+        None => History::Memory(vec![]),
+    };
 
     let mut layer_count = 0;
 
+    let mut resumed_layer = None;
+
+    if let (Some(checkpoint), Some(_)) = (&checkpoint, &opt.resume) {
+        let (index, output_sets, mut provenances) =
+            checkpoint.load_latest::<V>().unwrap_or_else(|err| {
+                log::error!("cannot resume from checkpoint: {}", err);
+                std::process::exit(1);
+            });
+        log::info!("resuming from layer {}", index);
+
+        layer = output_sets
+            .into_iter()
+            .zip(provenances.pop().unwrap())
+            .map(|(output_set, provenance)| {
+                AbstractedPair::new(output_set, (provenance, Witnesses::default()))
+            })
+            .collect();
+        for provenances in provenances {
+            history.push(provenances);
+        }
+        layer_count = index;
+        resumed_layer = Some(index);
+    }
+
+    let mut certificate = opt.certificate.as_ref().map(|path| {
+        CertificateWriter::new(BufWriter::new(File::create(path).unwrap()), opt.width).unwrap()
+    });
+    let mut parent_count = 0;
+
+    let hasher = RandomState::new();
+    let mut previous_hashes = None;
+
     while !layer.is_empty() {
         log::info!("layer {} size: {}", layer_count, layer.len());
+
+        // Once a layer repeats, all following layers do as well and the target is never reached.
+        // Comparing the hashes of the output sets avoids keeping the previous layer around.
+        let mut hashes = layer
+            .par_iter()
+            .map(|pair| hasher.hash_one(&pair.output_set))
+            .collect::<Vec<_>>();
+        hashes.par_sort_unstable();
+        if previous_hashes.as_ref() == Some(&hashes) {
+            log::error!(
+                "layer {} repeats the previous layer, target {} cannot be reached",
+                layer_count,
+                opt.target
+            );
+            break;
+        }
+        previous_hashes = Some(hashes);
+
+        if let Some(dir) = &opt.save_layers {
+            let path = dir.join(format!("layer-{}.bin", layer_count));
+            let file = BufWriter::new(File::create(&path).unwrap());
+            write_layer(
+                file,
+                layer_count,
+                opt.width,
+                layer.iter().map(|pair| &pair.output_set),
+            )
+            .unwrap();
+        }
+
+        if let Some(checkpoint) = &checkpoint {
+            if resumed_layer != Some(layer_count) {
+                let provenances = layer
+                    .iter()
+                    .map(|pair| pair.item.0.clone())
+                    .collect::<Vec<_>>();
+                checkpoint
+                    .save_layer(
+                        layer_count,
+                        opt.width,
+                        layer.iter().map(|pair| &pair.output_set),
+                        &provenances,
+                    )
+                    .unwrap();
+            }
+        }
+
+        if let Some(certificate) = &mut certificate {
+            let witnesses = collect_witnesses(
+                parent_count * (opt.width * opt.width.saturating_sub(1) / 2),
+                layer.iter().map(|pair| &pair.item.1),
+            );
+            certificate
+                .write_layer(layer.iter().map(|pair| &pair.output_set), &witnesses)
+                .unwrap();
+        }
+        parent_count = layer.len();
+
+        if let Some(index) = layer
+            .iter()
+            .position(|pair| opt.target.is_satisfied(&pair.output_set))
+        {
+            log::info!("layer {} reaches target {}", layer_count, opt.target);
+
+            history.push(layer.into_iter().map(|pair| pair.item.0).collect());
+            let mut network = history.reconstruct(opt.width, index);
+            if !restricted {
+                network = network.untangle();
+            }
+            assert!(opt.target.is_satisfied(&network.apply(&input)));
+
+            log::info!(
+                "network with size {} and depth {}: {}",
+                network.size(),
+                network.depth(),
+                network
+            );
+            break;
+        }
+
+        if shutdown.load(Ordering::Relaxed) {
+            if let Some(coordinator) = coordinator {
+                coordinator.finish().unwrap();
+            }
+            log::info!("stopped after saving layer {}", layer_count);
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+
         layer_count += 1;
 
-        let progress = ProgressBar::new(layer.len() as u64);
+        let layer_len = layer.len();
+
+        let (provenances, parents): (Vec<_>, Vec<_>) = layer
+            .into_iter()
+            .enumerate()
+            .map(|(index, pair)| (pair.item.0, (index, pair.output_set)))
+            .unzip();
+
+        history.push(provenances);
+
+        let saved_progress = match &checkpoint {
+            Some(checkpoint) if resumed_layer == Some(layer_count - 1) => checkpoint
+                .load_progress::<V>(layer_count, opt.width)
+                .unwrap_or_else(|err| {
+                    log::error!("cannot resume from checkpoint: {}", err);
+                    std::process::exit(1);
+                }),
+            _ => None,
+        };
+
+        let state = match saved_progress {
+            Some(progress) => {
+                log::info!(
+                    "resuming layer {} with {} inputs left",
+                    layer_count,
+                    progress.inputs.len()
+                );
+                let with_witnesses = |pairs: Vec<AbstractedPair<Provenance, V>>| {
+                    pairs
+                        .into_iter()
+                        .map(|pair| AbstractedPair {
+                            abstraction: pair.abstraction,
+                            output_set: pair.output_set,
+                            item: (pair.item, Witnesses::default()),
+                        })
+                        .collect()
+                };
+                IncrementalState {
+                    minimal: with_witnesses(progress.minimal),
+                    spilled: with_witnesses(progress.spilled),
+                    inputs: progress.inputs,
+                    chunk_size: progress.chunk_size,
+                }
+            }
+            None => IncrementalState::new(parents),
+        };
+
+        let save = |state: &IncrementalState<_, V, _>| {
+            if let Some(checkpoint) = &checkpoint {
+                checkpoint
+                    .save_progress(layer_count, state, |item: &(Provenance, Witnesses)| &item.0)
+                    .unwrap();
+                log::info!(
+                    "saved progress of layer {} with {} inputs left",
+                    layer_count,
+                    state.inputs.len()
+                );
+            }
+        };
+
+        let save_progress = checkpoint
+            .as_ref()
+            .map(|_| (Duration::from_secs(opt.checkpoint_interval), &save));
+
+        let progress = ProgressBar::new(layer_len as u64);
+        progress.set_position((layer_len - state.inputs.len()) as u64);
 
         let template = "{elapsed_precise} [{wide_bar:.green/blue}] {percent}% {pos}/{len} {eta}";
 
@@ -46,30 +618,288 @@ fn main() {
 
         progress.enable_steady_tick(100);
 
-        layer = incremental_minimal_elements(layer, |AbstractedPair { output_set, .. }| {
+        let seen = Seen::new();
+        // Output sets seen for another parent carry no witness, so they are left to the
+        // subsumption checks when writing a certificate. With a layer store, the seen output sets
+        // would take more memory than the layer itself.
+        let seen = if opt.canonical && opt.certificate.is_none() && store.is_none() {
+            Some(&seen)
+        } else {
+            None
+        };
+
+        let generator = |input| {
             progress.inc(1);
+            successors(opt, topology, input, seen)
+        };
+
+        let minimal = match (&mut coordinator, &store) {
+            (Some(coordinator), _) => coordinator
+                .minimal_elements(
+                    state,
+                    &symmetries,
+                    |len| progress.inc(len as u64),
+                    save_progress,
+                    &shutdown,
+                )
+                .unwrap_or_else(|err| {
+                    log::error!(
+                        "cannot generate layer {} with workers: {}",
+                        layer_count,
+                        err
+                    );
+                    std::process::exit(1);
+                }),
+            (_, Some(store)) => resume_minimal_elements_in(
+                store,
+                state,
+                &symmetries,
+                generator,
+                save_progress,
+                &shutdown,
+            ),
+            (None, None) if opt.stratified => Ok(stratified_minimal_elements(
+                state.inputs,
+                &symmetries,
+                generator,
+            )),
+            (None, None) => {
+                resume_minimal_elements(state, &symmetries, generator, save_progress, &shutdown)
+            }
+        };
 
-            let mut next_output_sets = vec![];
-            let implications = output_set.implications();
-            for j in 0..opt.width {
-                for i in 0..j {
-                    if implications.is_associated(i, j) {
-                        continue;
-                    }
-                    let mut next_output_set = output_set.apply_comparator(i, j);
-                    next_output_set.order_channels_by_weight();
-                    next_output_sets.push(next_output_set);
+        layer = match minimal {
+            Ok(layer) => layer,
+            Err(state) => {
+                progress.finish_and_clear();
+                save(&state);
+                if let Some(coordinator) = coordinator {
+                    coordinator.finish().unwrap();
                 }
+                log::info!("stopped during layer {}", layer_count);
+                std::process::exit(EXIT_INTERRUPTED);
             }
-            next_output_sets.sort_unstable();
-            next_output_sets.dedup();
-            next_output_sets
-                .into_iter()
-                .map(|output_set| AbstractedPair::new(output_set, ()))
-                .par_bridge()
-                .collect::<Vec<_>>()
-        });
+        };
 
         progress.finish();
     }
+
+    if let Some(certificate) = certificate {
+        certificate.finish().unwrap();
+    }
+
+    if let Some(coordinator) = coordinator {
+        coordinator.finish().unwrap();
+    }
+}
+
+/// Provenance of the finished layers, needed to reconstruct a network at the end.
+///
+/// With a layer store, each layer is written to a file and only read back for the reconstruction.
+enum History {
+    Memory(Vec<Vec<Provenance>>),
+    Files(ProvenanceFiles),
+}
+
+impl History {
+    fn push(&mut self, provenances: Vec<Provenance>) {
+        match self {
+            History::Memory(layers) => layers.push(provenances),
+            History::Files(files) => files.push(&provenances).unwrap_or_else(|err| {
+                log::error!("cannot store provenance in layer store: {}", err);
+                std::process::exit(1);
+            }),
+        }
+    }
+
+    /// Builds a network reaching the output set at `index` of the last layer.
+    fn reconstruct(&self, channels: usize, index: usize) -> Network {
+        match self {
+            History::Memory(layers) => reconstruct(channels, layers[1..].iter(), index),
+            History::Files(files) => {
+                let layers = (1..files.len()).map(|layer| {
+                    files.load(layer).unwrap_or_else(|err| {
+                        log::error!("cannot read provenance from layer store: {}", err);
+                        std::process::exit(1);
+                    })
+                });
+                reconstruct(channels, layers, index)
+            }
+        }
+    }
+}
+
+/// Output sets generated so far for a layer, split into shards by hash so that concurrent
+/// generators rarely wait for the same lock.
+struct Seen<V: Value> {
+    hasher: RandomState,
+    shards: Vec<Mutex<HashSet<OutputSet<V>>>>,
+}
+
+impl<V: Value> Seen<V> {
+    fn new() -> Self {
+        Self {
+            hasher: RandomState::new(),
+            shards: (0..SEEN_SHARDS)
+                .map(|_| Mutex::new(HashSet::new()))
+                .collect(),
+        }
+    }
+
+    /// Adds an output set and returns whether it was not seen before.
+    ///
+    /// Only output sets that were not seen before are cloned.
+    fn insert(&self, output_set: &OutputSet<V>) -> bool {
+        let hash = self.hasher.hash_one(output_set);
+        let mut shard = self.shards[hash as usize % SEEN_SHARDS].lock();
+        if shard.contains(output_set) {
+            false
+        } else {
+            shard.insert(output_set.clone());
+            true
+        }
+    }
+}
+
+/// Generates shards of layers for the coordinator at `addr` until it is done.
+fn work<V: Value>(opt: &Opt, topology: &Topology, symmetries: &Symmetries, addr: &str) {
+    // The coordinator may still be starting up.
+    let mut attempts = 0;
+    let stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(_) if attempts < 30 => {
+                attempts += 1;
+                std::thread::sleep(Duration::from_secs(1));
+            }
+            Err(err) => {
+                log::error!("cannot connect to coordinator {}: {}", addr, err);
+                std::process::exit(1);
+            }
+        }
+    };
+    log::info!("connected to coordinator {}", addr);
+
+    let mut shards = 0;
+    let result = run_worker::<_, V, _, _>(
+        stream,
+        &opt.search_config(),
+        opt.width,
+        symmetries,
+        |input| successors(opt, topology, input, None),
+        |_| shards += 1,
+    );
+
+    match result {
+        Ok(()) => log::info!("coordinator is done after {} shards", shards),
+        Err(err) => {
+            log::error!("cannot generate shards for coordinator {}: {}", addr, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Generates the successors of an output set of the previous layer by applying each comparator or,
+/// with `--depth`, each maximal layer of comparators.
+///
+/// With `--bitmap`, a dense output set is converted to a bitmap once and each successor is
+/// converted back. Output sets already in `seen` are dropped.
+fn successors<V: Value>(
+    opt: &Opt,
+    topology: &Topology,
+    (parent, output_set): (usize, OutputSet<V>),
+    seen: Option<&Seen<V>>,
+) -> Vec<AbstractedPair<(Provenance, Witnesses), V>> {
+    let restricted = !topology.is_complete();
+
+    let mut next_output_sets = vec![];
+    let implications = output_set.implications();
+    let bitmap = if opt.bitmap && BitmapOutputSet::is_dense(&output_set) {
+        Some(BitmapOutputSet::from(&output_set))
+    } else {
+        None
+    };
+    let comparators = if restricted {
+        topology
+            .comparators()
+            .into_iter()
+            .filter(|&(i, j)| !implications.implies(j, i))
+            .collect::<Vec<_>>()
+    } else {
+        let comparators = if opt.automorphisms {
+            let generators = automorphism_generators(&output_set);
+            comparator_representatives(&generators, opt.width)
+        } else {
+            (0..opt.width)
+                .flat_map(|j| (0..j).map(move |i| (i, j)))
+                .collect()
+        };
+        comparators
+            .into_iter()
+            .filter(|&(i, j)| !implications.is_associated(i, j))
+            .collect::<Vec<_>>()
+    };
+    let steps = if opt.depth {
+        maximal_layers(opt.width, &comparators)
+    } else {
+        comparators
+            .into_iter()
+            .map(|comparator| vec![comparator])
+            .collect()
+    };
+    for step in steps {
+        let mut next_output_set = match &bitmap {
+            Some(bitmap) => {
+                let mut next_bitmap = bitmap.apply_comparator(step[0].0, step[0].1);
+                for &(i, j) in step[1..].iter() {
+                    next_bitmap = next_bitmap.apply_comparator(i, j);
+                }
+                OutputSet::from(&next_bitmap)
+            }
+            None => {
+                let mut next_output_set = output_set.clone();
+                for &(i, j) in step.iter() {
+                    next_output_set = next_output_set.apply_comparator(i, j);
+                }
+                next_output_set
+            }
+        };
+        let perm = if opt.canonical {
+            let (canonical, perm) = next_output_set.canonical_form();
+            next_output_set = canonical;
+            perm
+        } else if !restricted {
+            next_output_set.order_channels_by_weight()
+        } else {
+            CVec::new()
+        };
+        let witnesses = if opt.certificate.is_some() {
+            let index = witness_index(opt.width, parent, step[0]);
+            Witnesses(vec![(index, Transform::new(false, &perm))])
+        } else {
+            Witnesses::default()
+        };
+        next_output_sets.push((
+            next_output_set,
+            (Provenance::new(parent, &step, &perm), witnesses),
+        ));
+    }
+    next_output_sets.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    next_output_sets.dedup_by(
+        |(output_set, (_, witnesses)), (kept, (_, kept_witnesses))| {
+            let duplicate = output_set == kept;
+            if duplicate {
+                kept_witnesses.0.append(&mut witnesses.0);
+            }
+            duplicate
+        },
+    );
+    if let Some(seen) = seen {
+        next_output_sets.retain(|(output_set, _)| seen.insert(output_set));
+    }
+    next_output_sets
+        .into_iter()
+        .map(|(output_set, item)| AbstractedPair::new(output_set, item))
+        .par_bridge()
+        .collect::<Vec<_>>()
 }
@@ -0,0 +1,241 @@
+use rayon::prelude::*;
+
+use crate::{
+    network::Network,
+    output_set::{CVec, OutputSet, MAX_CHANNELS},
+    value::Value,
+};
+
+/// Up to 64 inputs of a comparator network, stored as one word per channel.
+///
+/// Bit `k` of the word of channel `i` is the value on channel `i` for the `k`-th input. A
+/// comparator `(a, b)` then turns the word of channel `a` into the OR and the word of channel `b`
+/// into the AND of both words.
+#[derive(Clone, Debug)]
+pub struct BitSlice {
+    words: CVec<u64>,
+    lanes: u64,
+}
+
+impl BitSlice {
+    /// Number of slices needed to cover all `2^channels` inputs.
+    pub fn batches(channels: usize) -> usize {
+        (1usize << channels).div_ceil(64)
+    }
+
+    /// Inputs `64 * batch` up to `64 * batch + 63` of all `2^channels` inputs.
+    pub fn all_values_batch(channels: usize, batch: usize) -> Self {
+        assert!(channels <= MAX_CHANNELS);
+        assert!(batch < Self::batches(channels));
+
+        const PATTERNS: [u64; 6] = [
+            0xaaaa_aaaa_aaaa_aaaa,
+            0xcccc_cccc_cccc_cccc,
+            0xf0f0_f0f0_f0f0_f0f0,
+            0xff00_ff00_ff00_ff00,
+            0xffff_0000_ffff_0000,
+            0xffff_ffff_0000_0000,
+        ];
+
+        let words = (0..channels)
+            .map(|channel| {
+                if channel < 6 {
+                    PATTERNS[channel]
+                } else if (batch >> (channel - 6)) & 1 != 0 {
+                    !0
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        let lanes = if channels < 6 {
+            (1 << (1 << channels)) - 1
+        } else {
+            !0
+        };
+
+        Self { words, lanes }
+    }
+
+    pub fn from_values<V: Value>(channels: usize, values: &[V]) -> Self {
+        assert!(channels <= MAX_CHANNELS);
+        assert!(values.len() <= 64);
+
+        let mut words = (0..channels).map(|_| 0).collect::<CVec<u64>>();
+
+        for (lane, &value) in values.iter().enumerate() {
+            for (channel, word) in words.iter_mut().enumerate() {
+                *word |= (((value >> channel) & V::ONE).to_usize() as u64) << lane;
+            }
+        }
+
+        let lanes = if values.len() == 64 {
+            !0
+        } else {
+            (1 << values.len()) - 1
+        };
+
+        Self { words, lanes }
+    }
+
+    /// Mask of the lanes that hold an input.
+    pub fn lanes(&self) -> u64 {
+        self.lanes
+    }
+
+    pub fn word(&self, channel: usize) -> u64 {
+        self.words[channel]
+    }
+
+    pub fn apply_comparator(&mut self, a: usize, b: usize) {
+        let (word_a, word_b) = (self.words[a], self.words[b]);
+        self.words[a] = word_a | word_b;
+        self.words[b] = word_a & word_b;
+    }
+
+    pub fn apply_network(&mut self, network: &Network) {
+        assert_eq!(network.channels(), self.words.len());
+        for &(a, b) in network.comparators() {
+            self.apply_comparator(a, b);
+        }
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        self.words
+            .windows(2)
+            .all(|pair| pair[1] & !pair[0] & self.lanes == 0)
+    }
+
+    /// The inputs as values, in lane order and including duplicates.
+    pub fn values<V: Value>(&self) -> impl Iterator<Item = V> + '_ {
+        (0..64)
+            .filter(move |lane| (self.lanes >> lane) & 1 != 0)
+            .map(move |lane| {
+                self.words
+                    .iter()
+                    .enumerate()
+                    .fold(V::ZERO, |value, (channel, &word)| {
+                        if (word >> lane) & 1 != 0 {
+                            value | V::ONE << channel
+                        } else {
+                            value
+                        }
+                    })
+            })
+    }
+}
+
+/// Applies a network to an output set, processing 64 values at a time.
+pub fn apply<V: Value>(network: &Network, output_set: &OutputSet<V>) -> OutputSet<V> {
+    let channels = output_set.channels();
+    assert_eq!(network.channels(), channels);
+
+    let mut values = Vec::with_capacity(output_set.values().len());
+
+    for chunk in output_set.values().chunks(64) {
+        let mut slice = BitSlice::from_values(channels, chunk);
+        slice.apply_network(network);
+        values.extend(slice.values::<V>());
+    }
+
+    OutputSet::from_values(channels, values)
+}
+
+/// Output set of a network for all `2^channels` inputs.
+pub fn output_set<V: Value>(network: &Network) -> OutputSet<V> {
+    let channels = network.channels();
+    assert!(channels <= V::MAX_CHANNELS);
+
+    let batches = BitSlice::batches(channels);
+
+    let mut present = vec![0u64; batches];
+
+    for batch in 0..batches {
+        let mut slice = BitSlice::all_values_batch(channels, batch);
+        slice.apply_network(network);
+        for value in slice.values::<V>() {
+            let value = value.to_usize();
+            present[value / 64] |= 1 << (value % 64);
+        }
+    }
+
+    let values = present
+        .iter()
+        .enumerate()
+        .flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| (word >> bit) & 1 != 0)
+                .map(move |bit| V::from_usize(index * 64 + bit))
+        })
+        .collect();
+
+    OutputSet::from_values(channels, values)
+}
+
+/// Checks whether a network sorts all `2^channels` inputs.
+pub fn is_sorting_network(network: &Network) -> bool {
+    let channels = network.channels();
+    (0..BitSlice::batches(channels))
+        .into_par_iter()
+        .all(|batch| {
+            let mut slice = BitSlice::all_values_batch(channels, batch);
+            slice.apply_network(network);
+            slice.is_sorted()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn some_networks(channels: usize) -> Vec<Network> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+
+        let mut networks = vec![Network::new(channels, vec![])];
+        for size in 1..4 * channels {
+            let mut network = Network::new(channels, vec![]);
+            while network.size() < size {
+                let (a, b) = (next(channels), next(channels));
+                if a != b {
+                    network.push(a, b);
+                }
+            }
+            networks.push(network);
+        }
+        networks
+    }
+
+    #[test]
+    fn agrees_with_apply_comparator() {
+        for channels in 2..=9 {
+            for network in some_networks(channels) {
+                let all_values: OutputSet = OutputSet::all_values(channels);
+                let expected = network.apply(&all_values);
+
+                assert_eq!(output_set::<u16>(&network), expected);
+                assert_eq!(apply(&network, &all_values), expected);
+                assert_eq!(is_sorting_network(&network), expected.is_sorted());
+
+                let partial = all_values.apply_comparator(0, channels - 1);
+                assert_eq!(apply(&network, &partial), network.apply(&partial));
+            }
+        }
+    }
+
+    #[test]
+    fn wide_networks() {
+        let channels = 17;
+        for network in some_networks(channels).into_iter().step_by(16) {
+            let expected = network.apply(&OutputSet::<u32>::all_values(channels));
+            assert_eq!(output_set::<u32>(&network), expected);
+            assert_eq!(is_sorting_network(&network), expected.is_sorted());
+        }
+    }
+}
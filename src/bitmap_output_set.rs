@@ -0,0 +1,289 @@
+use std::marker::PhantomData;
+
+use crate::{
+    output_set::{CVec, OutputSet},
+    value::Value,
+};
+
+/// An output set stored as a bitmap with one bit for each of the `2^channels` possible values.
+///
+/// For dense output sets this is smaller than the sorted list of values used by [`OutputSet`] and
+/// it allows applying comparators and checking subsumption using bit operations on whole words.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct BitmapOutputSet<V: Value = u16> {
+    channels: usize,
+    words: Vec<u64>,
+    value: PhantomData<V>,
+}
+
+impl<V: Value> BitmapOutputSet<V> {
+    pub fn all_values(channels: usize) -> Self {
+        assert!(channels <= V::MAX_CHANNELS);
+        let mut words = vec![!0; Self::words_len(channels)];
+        if channels < 6 {
+            words[0] = (1 << (1 << channels)) - 1;
+        }
+        Self::from_words(channels, words)
+    }
+
+    /// Returns whether the bitmap representation of an output set is smaller than the sorted list
+    /// of values.
+    pub fn is_dense(output_set: &OutputSet<V>) -> bool {
+        output_set.values().len() * 16 >= 1 << output_set.channels()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn len(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    pub fn contains(&self, value: V) -> bool {
+        let value = value.to_usize();
+        self.words[value / 64] & (1 << (value % 64)) != 0
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = V> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .flat_map(|(word_index, &word)| {
+                let mut rest = word;
+                std::iter::from_fn(move || {
+                    if rest == 0 {
+                        return None;
+                    }
+                    let bit = rest.trailing_zeros() as usize;
+                    rest &= rest - 1;
+                    Some(V::from_usize(word_index * 64 + bit))
+                })
+            })
+    }
+
+    pub fn apply_comparator(&self, a: usize, b: usize) -> Self {
+        assert_ne!(a, b);
+        assert!(a < self.channels && b < self.channels);
+
+        let swap = self.select(b, a);
+        let mut result = self.shifted(&swap, (1 << a) - (1 << b));
+
+        for (word, (&self_word, &swap_word)) in result
+            .words
+            .iter_mut()
+            .zip(self.words.iter().zip(swap.iter()))
+        {
+            *word |= self_word & !swap_word;
+        }
+
+        result
+    }
+
+    pub fn swap_channels(&mut self, a: usize, b: usize) {
+        assert!(a < self.channels && b < self.channels);
+        if a == b {
+            return;
+        }
+
+        let swap_a = self.select(a, b);
+        let swap_b = self.select(b, a);
+
+        let mut result = self.shifted(&swap_a, (1 << b) - (1 << a));
+        let result_b = self.shifted(&swap_b, (1 << a) - (1 << b));
+
+        for (word, (((&self_word, &swap_a_word), &swap_b_word), &result_b_word)) in
+            result.words.iter_mut().zip(
+                self.words
+                    .iter()
+                    .zip(swap_a.iter())
+                    .zip(swap_b.iter())
+                    .zip(result_b.words.iter()),
+            )
+        {
+            *word |= result_b_word | (self_word & !(swap_a_word | swap_b_word));
+        }
+
+        *self = result;
+    }
+
+    pub fn subsumes(&self, other: &BitmapOutputSet<V>) -> bool {
+        assert_eq!(self.channels, other.channels);
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .all(|(&my, &other)| my & !other == 0)
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        let mut sorted = Self::from_words(self.channels, vec![0; self.words.len()]);
+        for ones in 0..=self.channels {
+            let value = (1 << ones) - 1;
+            sorted.words[value / 64] |= 1 << (value % 64);
+        }
+        self.subsumes(&sorted)
+    }
+
+    pub fn channel_weights(&self) -> CVec<V> {
+        (0..self.channels)
+            .map(|channel| {
+                self.words
+                    .iter()
+                    .enumerate()
+                    .map(|(word_index, &word)| {
+                        V::from_usize(
+                            (word & Self::channel_mask(channel, word_index)).count_ones() as usize,
+                        )
+                    })
+                    .fold(V::ZERO, |sum, weight| sum + weight)
+            })
+            .collect()
+    }
+
+    /// Returns the members that have the channel `set` set and the channel `clear` cleared.
+    fn select(&self, set: usize, clear: usize) -> Vec<u64> {
+        self.words
+            .iter()
+            .enumerate()
+            .map(|(word_index, &word)| {
+                word & Self::channel_mask(set, word_index) & !Self::channel_mask(clear, word_index)
+            })
+            .collect()
+    }
+
+    /// Moves every member of `words` from value `v` to value `v + delta`.
+    fn shifted(&self, words: &[u64], delta: isize) -> Self {
+        let mut result = vec![0; words.len()];
+
+        let word_shift = delta.unsigned_abs() / 64;
+        let bit_shift = delta.unsigned_abs() % 64;
+
+        for (word_index, &word) in words.iter().enumerate() {
+            if word == 0 {
+                continue;
+            }
+            if delta > 0 {
+                result[word_index + word_shift] |= word << bit_shift;
+                if bit_shift != 0 && word_index + word_shift + 1 < result.len() {
+                    result[word_index + word_shift + 1] |= word >> (64 - bit_shift);
+                }
+            } else {
+                result[word_index - word_shift] |= word >> bit_shift;
+                if bit_shift != 0 && word_index > word_shift {
+                    result[word_index - word_shift - 1] |= word << (64 - bit_shift);
+                }
+            }
+        }
+
+        Self::from_words(self.channels, result)
+    }
+
+    /// Returns the bits of the word at `word_index` that represent values with `channel` set.
+    fn channel_mask(channel: usize, word_index: usize) -> u64 {
+        const IN_WORD: [u64; 6] = [
+            0xaaaa_aaaa_aaaa_aaaa,
+            0xcccc_cccc_cccc_cccc,
+            0xf0f0_f0f0_f0f0_f0f0,
+            0xff00_ff00_ff00_ff00,
+            0xffff_0000_ffff_0000,
+            0xffff_ffff_0000_0000,
+        ];
+        if channel < 6 {
+            IN_WORD[channel]
+        } else if (word_index >> (channel - 6)) & 1 != 0 {
+            !0
+        } else {
+            0
+        }
+    }
+
+    fn from_words(channels: usize, words: Vec<u64>) -> Self {
+        Self {
+            channels,
+            words,
+            value: PhantomData,
+        }
+    }
+
+    fn words_len(channels: usize) -> usize {
+        (1usize << channels).div_ceil(64)
+    }
+}
+
+impl<V: Value> From<&OutputSet<V>> for BitmapOutputSet<V> {
+    fn from(output_set: &OutputSet<V>) -> Self {
+        let mut words = vec![0; Self::words_len(output_set.channels())];
+        for &value in output_set.values() {
+            let value = value.to_usize();
+            words[value / 64] |= 1 << (value % 64);
+        }
+        Self::from_words(output_set.channels(), words)
+    }
+}
+
+impl<V: Value> From<&BitmapOutputSet<V>> for OutputSet<V> {
+    fn from(bitmap: &BitmapOutputSet<V>) -> Self {
+        OutputSet::from_values(bitmap.channels, bitmap.values().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn agrees_with_output_set() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+
+        for channels in 2..=9 {
+            let mut output_set: OutputSet = OutputSet::all_values(channels);
+            let mut bitmap = BitmapOutputSet::all_values(channels);
+
+            assert_eq!(bitmap, BitmapOutputSet::from(&output_set));
+
+            for _ in 0..channels * channels {
+                let a = random(channels);
+                let b = (a + 1 + random(channels - 1)) % channels;
+
+                let next_output_set = output_set.apply_comparator(a, b);
+                let next_bitmap = bitmap.apply_comparator(a, b);
+
+                assert_eq!(OutputSet::from(&next_bitmap), next_output_set);
+                assert_eq!(next_bitmap.len(), next_output_set.values().len());
+                assert_eq!(
+                    next_bitmap.channel_weights(),
+                    next_output_set.channel_weights()
+                );
+                assert_eq!(next_bitmap.is_sorted(), next_output_set.is_sorted());
+                assert_eq!(
+                    next_bitmap.subsumes(&bitmap),
+                    next_output_set.subsumes(&output_set)
+                );
+
+                output_set = next_output_set;
+                bitmap = next_bitmap;
+
+                let c = random(channels);
+                let d = random(channels);
+
+                output_set.swap_channels(c, d);
+                bitmap.swap_channels(c, d);
+
+                assert_eq!(OutputSet::from(&bitmap), output_set);
+            }
+        }
+    }
+}
@@ -0,0 +1,531 @@
+use std::{
+    io::{self, BufRead, Read, Write},
+    marker::PhantomData,
+};
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    layer_file::{
+        invalid_data, read_u32, read_u64, read_varint, write_layer, write_varint, ChecksumReader,
+        ChecksumWriter, Fnv1a, LayerReader,
+    },
+    layer_store::StoreItem,
+    output_set::{CVec, OutputSet, MAX_CHANNELS},
+    subsume_index::SubsumeIndexItem,
+    value::Value,
+};
+
+const MAGIC: &[u8; 8] = b"SNOCERTF";
+
+const VERSION: u32 = 1;
+
+/// Reflection followed by a channel permutation, as used to witness a subsumption.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Transform {
+    pub reflected: bool,
+    /// Permutation as passed to [`OutputSet::permute_channels`].
+    pub perm: ArrayVec<[u8; MAX_CHANNELS]>,
+}
+
+impl Transform {
+    pub fn new(reflected: bool, perm: &[usize]) -> Self {
+        Self {
+            reflected,
+            perm: perm.iter().map(|&channel| channel as u8).collect(),
+        }
+    }
+
+    pub fn identity(channels: usize) -> Self {
+        Self::new(false, &(0..channels).collect::<CVec<_>>())
+    }
+
+    /// The transform that first applies `self` and then reflects if `reflected` is set and
+    /// permutes by `perm`.
+    pub fn then(&self, reflected: bool, perm: &[usize]) -> Self {
+        let channels = self.perm.len();
+        assert_eq!(perm.len(), channels);
+
+        // Reflecting after permuting by `p` is the same as permuting by the reversed conjugate of
+        // `p` after reflecting.
+        let first = |channel: usize| {
+            if reflected {
+                channels - 1 - self.perm[channels - 1 - channel] as usize
+            } else {
+                self.perm[channel] as usize
+            }
+        };
+
+        Self {
+            reflected: self.reflected ^ reflected,
+            perm: perm.iter().map(|&channel| first(channel) as u8).collect(),
+        }
+    }
+
+    pub fn apply<V: Value>(&self, output_set: &OutputSet<V>) -> OutputSet<V> {
+        let mut result = if self.reflected {
+            output_set.reflect()
+        } else {
+            output_set.clone()
+        };
+        result.permute_channels(self.perm.iter().map(|&channel| channel as usize).collect());
+        result
+    }
+}
+
+/// Records which generated output sets were combined into an output set, see
+/// [`collect_witnesses`].
+///
+/// Each entry is the index of the generated output set, as returned by [`witness_index`], and a
+/// transform that turns the generated output set into a superset of the output set holding the
+/// entry.
+#[derive(Clone, Debug, Default)]
+pub struct Witnesses(pub Vec<(usize, Transform)>);
+
+impl SubsumeIndexItem for Witnesses {
+    fn combine(&mut self, perm: CVec<usize>, reflected: bool, other: Self) {
+        self.0.extend(
+            other
+                .0
+                .into_iter()
+                .map(|(index, transform)| (index, transform.then(reflected, &perm))),
+        );
+    }
+}
+
+impl StoreItem for Witnesses {
+    fn write_item<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_varint(writer, self.0.len() as u64)?;
+        for (index, transform) in self.0.iter() {
+            write_varint(writer, *index as u64)?;
+            writer.write_all(&[transform.reflected as u8, transform.perm.len() as u8])?;
+            writer.write_all(&transform.perm)?;
+        }
+        Ok(())
+    }
+
+    fn read_item<R: Read>(reader: &mut R, _channels: usize) -> io::Result<Self> {
+        let len = read_varint(reader)? as usize;
+        let mut witnesses = Vec::with_capacity(len.min(1 << 20));
+        for _ in 0..len {
+            let index = read_varint(reader)? as usize;
+            let mut header = [0; 2];
+            reader.read_exact(&mut header)?;
+            if header[1] as usize > MAX_CHANNELS {
+                return Err(invalid_data("too many channels in witness"));
+            }
+            let mut perm = [0; MAX_CHANNELS];
+            let perm = &mut perm[..header[1] as usize];
+            reader.read_exact(perm)?;
+            witnesses.push((
+                index,
+                Transform {
+                    reflected: header[0] != 0,
+                    perm: perm.iter().copied().collect(),
+                },
+            ));
+        }
+        Ok(Witnesses(witnesses))
+    }
+}
+
+/// A surviving output set of the next layer that subsumes a generated output set.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Witness {
+    pub survivor: usize,
+    pub transform: Transform,
+}
+
+/// The standard comparators in the order used for the witnesses of a layer, ordered by the larger
+/// channel and then by the smaller channel.
+pub fn comparators(channels: usize) -> Vec<(usize, usize)> {
+    (0..channels)
+        .flat_map(|j| (0..j).map(move |i| (i, j)))
+        .collect()
+}
+
+/// Position of the output set generated by applying comparator `(i, j)` to the output set at
+/// index `parent` of the previous layer.
+pub fn witness_index(channels: usize, parent: usize, (i, j): (usize, usize)) -> usize {
+    assert!(i < j && j < channels);
+    parent * (channels * (channels - 1) / 2) + j * (j - 1) / 2 + i
+}
+
+/// Builds the witnesses of a layer from the items of its surviving output sets.
+///
+/// Generated output sets that were skipped, because their comparator never or always swaps, are
+/// left as `None`.
+pub fn collect_witnesses<'a>(
+    len: usize,
+    items: impl Iterator<Item = &'a Witnesses>,
+) -> Vec<Option<Witness>> {
+    let mut witnesses = vec![None; len];
+    for (survivor, item) in items.enumerate() {
+        for (index, transform) in item.0.iter() {
+            assert!(witnesses[*index].is_none());
+            witnesses[*index] = Some(Witness {
+                survivor,
+                transform: transform.clone(),
+            });
+        }
+    }
+    witnesses
+}
+
+/// Writes a certificate for the layers of a search.
+///
+/// A certificate starts with the magic bytes `SNOCERTF`, followed by the format version and the
+/// number of channels as little endian `u32`. Each layer follows as a complete layer file, see
+/// [`layer_file`](crate::layer_file), and a witness block. The witness block has one entry for
+/// every standard comparator applied to every output set of the previous layer, in the order of
+/// [`witness_index`]. It starts with the number of entries as little endian `u64`. Each entry is
+/// a LEB128 varint that is zero for a comparator that never or always swaps, and otherwise the
+/// index of the surviving output set plus one, followed by a reflection flag byte and the
+/// permutation as one byte per channel. The block ends with a 64-bit FNV-1a checksum of the
+/// block.
+///
+/// Following the witnesses, every output set of a network of size `k` is a superset of a
+/// transformed output set of one of the layers `0..=k`.
+pub struct CertificateWriter<W: Write> {
+    writer: W,
+    channels: usize,
+    layers: usize,
+    parents: usize,
+}
+
+impl<W: Write> CertificateWriter<W> {
+    pub fn new(mut writer: W, channels: usize) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(channels as u32).to_le_bytes())?;
+        Ok(Self {
+            writer,
+            channels,
+            layers: 0,
+            parents: 0,
+        })
+    }
+
+    /// Writes the next layer and the witnesses for generating it from the previous layer.
+    pub fn write_layer<'a, V: Value>(
+        &mut self,
+        output_sets: impl ExactSizeIterator<Item = &'a OutputSet<V>>,
+        witnesses: &[Option<Witness>],
+    ) -> io::Result<()> {
+        let channels = self.channels;
+        assert_eq!(
+            witnesses.len(),
+            self.parents * (channels * channels.saturating_sub(1) / 2)
+        );
+
+        let len = output_sets.len();
+        write_layer(&mut self.writer, self.layers, channels, output_sets)?;
+
+        let mut writer = ChecksumWriter {
+            inner: &mut self.writer,
+            checksum: Fnv1a::default(),
+        };
+        writer.write_all(&(witnesses.len() as u64).to_le_bytes())?;
+        for witness in witnesses.iter() {
+            match witness {
+                None => write_varint(&mut writer, 0)?,
+                Some(Witness {
+                    survivor,
+                    transform,
+                }) => {
+                    assert!(*survivor < len);
+                    assert_eq!(transform.perm.len(), channels);
+                    write_varint(&mut writer, *survivor as u64 + 1)?;
+                    writer.write_all(&[transform.reflected as u8])?;
+                    writer.write_all(&transform.perm)?;
+                }
+            }
+        }
+        let checksum = writer.checksum.0;
+        self.writer.write_all(&checksum.to_le_bytes())?;
+
+        self.layers += 1;
+        self.parents = len;
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// A layer of a certificate together with the witnesses for generating it.
+#[derive(Clone, Debug)]
+pub struct CertificateLayer<V: Value = u16> {
+    pub layer: usize,
+    pub output_sets: Vec<OutputSet<V>>,
+    pub witnesses: Vec<Option<Witness>>,
+}
+
+/// Reads a certificate one layer at a time.
+///
+/// This checks that the certificate is well-formed, but not that the witnesses are valid.
+pub struct CertificateReader<R: BufRead, V: Value = u16> {
+    reader: R,
+    channels: usize,
+    layers: usize,
+    parents: usize,
+    value: PhantomData<V>,
+}
+
+impl<R: BufRead, V: Value> CertificateReader<R, V> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a certificate"));
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(invalid_data("unsupported certificate version"));
+        }
+
+        let channels = read_u32(&mut reader)? as usize;
+        if channels > V::MAX_CHANNELS {
+            return Err(invalid_data("too many channels for the value type"));
+        }
+
+        Ok(Self {
+            reader,
+            channels,
+            layers: 0,
+            parents: 0,
+            value: PhantomData,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Reads the next layer, or returns `None` at the end of the certificate.
+    pub fn next_layer(&mut self) -> io::Result<Option<CertificateLayer<V>>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let channels = self.channels;
+
+        let layer_reader = LayerReader::<_, V>::new(&mut self.reader)?;
+        let header = *layer_reader.header();
+        if header.channels != channels || header.layer != self.layers {
+            return Err(invalid_data("unexpected layer header"));
+        }
+        let output_sets = layer_reader.collect::<io::Result<Vec<_>>>()?;
+
+        let mut reader = ChecksumReader {
+            inner: &mut self.reader,
+            checksum: Fnv1a::default(),
+        };
+
+        let len = read_u64(&mut reader)? as usize;
+        if len != self.parents * (channels * channels.saturating_sub(1) / 2) {
+            return Err(invalid_data("unexpected number of witnesses"));
+        }
+
+        let mut witnesses = Vec::with_capacity(len);
+        for _ in 0..len {
+            let survivor = read_varint(&mut reader)? as usize;
+            if survivor == 0 {
+                witnesses.push(None);
+                continue;
+            }
+            let survivor = survivor - 1;
+            if survivor >= output_sets.len() {
+                return Err(invalid_data("witness refers to a missing output set"));
+            }
+
+            let mut reflected = [0];
+            reader.read_exact(&mut reflected)?;
+            let mut perm = ArrayVec::<[u8; MAX_CHANNELS]>::new();
+            for _ in 0..channels {
+                let mut channel = [0];
+                reader.read_exact(&mut channel)?;
+                perm.push(channel[0]);
+            }
+
+            let mut used = 0u64;
+            for &channel in perm.iter() {
+                if channel as usize >= channels || used & (1 << channel) != 0 {
+                    return Err(invalid_data("witness permutation is invalid"));
+                }
+                used |= 1 << channel;
+            }
+            if reflected[0] > 1 {
+                return Err(invalid_data("witness reflection flag is invalid"));
+            }
+
+            witnesses.push(Some(Witness {
+                survivor,
+                transform: Transform {
+                    reflected: reflected[0] == 1,
+                    perm,
+                },
+            }));
+        }
+
+        let expected = reader.checksum.0;
+        if read_u64(&mut self.reader)? != expected {
+            return Err(invalid_data("witness checksum mismatch"));
+        }
+
+        self.layers += 1;
+        self.parents = output_sets.len();
+
+        Ok(Some(CertificateLayer {
+            layer: header.layer,
+            output_sets,
+            witnesses,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::subsume_index::{incremental_minimal_elements, AbstractedPair, Symmetries};
+
+    #[test]
+    fn compose_transforms() {
+        let channels = 6;
+        let output_set = OutputSet::<u16>::all_values(channels)
+            .apply_comparator(0, 4)
+            .apply_comparator(2, 3)
+            .apply_comparator(1, 4);
+
+        let perms: [CVec<usize>; 2] = [
+            [3, 0, 5, 1, 2, 4].iter().cloned().collect(),
+            [1, 2, 0, 5, 4, 3].iter().cloned().collect(),
+        ];
+
+        for &first_reflected in [false, true].iter() {
+            for &second_reflected in [false, true].iter() {
+                let first = Transform::new(first_reflected, &perms[0]);
+                let composed = first.then(second_reflected, &perms[1]);
+                let second = Transform::new(second_reflected, &perms[1]);
+                assert_eq!(
+                    composed.apply(&output_set),
+                    second.apply(&first.apply(&output_set))
+                );
+            }
+        }
+
+        assert_eq!(Transform::identity(channels).apply(&output_set), output_set);
+    }
+
+    /// Generates the layers of a search and checks the recorded witnesses the way an independent
+    /// checker would.
+    #[test]
+    fn witnesses_round_trip() {
+        let channels = 5;
+        let symmetries = Symmetries {
+            reflection: true,
+            permutations: None,
+        };
+
+        let mut writer = CertificateWriter::new(vec![], channels).unwrap();
+
+        let mut layer = vec![AbstractedPair::new(
+            OutputSet::<u16>::all_values(channels),
+            Witnesses::default(),
+        )];
+        let mut layers = vec![];
+
+        for _ in 0..5 {
+            let parents = layers
+                .last()
+                .map_or(0, |layer: &Vec<OutputSet>| layer.len());
+            let witnesses = collect_witnesses(
+                parents * channels * (channels - 1) / 2,
+                layer.iter().map(|pair| &pair.item),
+            );
+            writer
+                .write_layer(layer.iter().map(|pair| &pair.output_set), &witnesses)
+                .unwrap();
+
+            let output_sets = layer
+                .into_iter()
+                .map(|pair| pair.output_set)
+                .collect::<Vec<_>>();
+            layers.push(output_sets.clone());
+
+            layer = incremental_minimal_elements(
+                output_sets.into_iter().enumerate().collect(),
+                &symmetries,
+                |(parent, output_set)| {
+                    let implications = output_set.implications();
+                    comparators(channels)
+                        .into_iter()
+                        .filter(|&(i, j)| !implications.is_associated(i, j))
+                        .map(|comparator| {
+                            let mut next = output_set.apply_comparator(comparator.0, comparator.1);
+                            let perm = next.order_channels_by_weight();
+                            let index = witness_index(channels, parent, comparator);
+                            AbstractedPair::new(
+                                next,
+                                Witnesses(vec![(index, Transform::new(false, &perm))]),
+                            )
+                        })
+                        .collect()
+                },
+            );
+        }
+
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = CertificateReader::<_, u16>::new(&bytes[..]).unwrap();
+        assert_eq!(reader.channels(), channels);
+
+        let mut previous: Option<Vec<OutputSet>> = None;
+        let mut count = 0;
+        while let Some(certificate_layer) = reader.next_layer().unwrap() {
+            assert_eq!(certificate_layer.output_sets, layers[count]);
+
+            let parents = previous.as_deref().unwrap_or(&[]);
+            let pairs = comparators(channels);
+            assert_eq!(
+                certificate_layer.witnesses.len(),
+                parents.len() * pairs.len()
+            );
+
+            for (index, witness) in certificate_layer.witnesses.iter().enumerate() {
+                let parent = &parents[index / pairs.len()];
+                let (i, j) = pairs[index % pairs.len()];
+                match witness {
+                    None => assert!(parent.implications().is_associated(i, j)),
+                    Some(witness) => {
+                        let generated = witness.transform.apply(&parent.apply_comparator(i, j));
+                        let survivor = &certificate_layer.output_sets[witness.survivor];
+                        assert!(survivor.subsumes(&generated));
+                    }
+                }
+            }
+
+            previous = Some(certificate_layer.output_sets);
+            count += 1;
+        }
+        assert_eq!(count, layers.len());
+
+        let mut corrupted = bytes;
+        let last = corrupted.len() - 9;
+        corrupted[last] ^= 1;
+        let mut reader = CertificateReader::<_, u16>::new(&corrupted[..]).unwrap();
+        let mut result = Ok(None);
+        for _ in 0..count {
+            result = reader.next_layer();
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(result.is_err());
+    }
+}
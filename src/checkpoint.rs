@@ -0,0 +1,430 @@
+use std::{
+    fmt, fs,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    layer_file::{
+        invalid_data, read_layer, read_output_set, read_u64, read_varint, write_layer,
+        write_output_set, write_varint, ChecksumReader, ChecksumWriter, Fnv1a,
+    },
+    output_set::OutputSet,
+    provenance::{
+        read_provenance, read_provenances, write_provenance, write_provenances, Provenance,
+    },
+    subsume_index::{AbstractedPair, IncrementalState},
+    value::Value,
+};
+
+const CONFIG: &str = "config.txt";
+
+/// State of generating a layer, with the item of each output set reduced to its provenance and
+/// each input being a parent output set with its index.
+pub type LayerProgress<V> = IncrementalState<Provenance, V, (usize, OutputSet<V>)>;
+
+/// Directory holding the finished layers of a search, so that it can be resumed.
+///
+/// The directory contains `config.txt`, describing the options of the search, and for each
+/// finished layer `k` the file `provenance-<k>.bin` with the provenance of its output sets. Only
+/// the newest layer is kept, as `checkpoint-<k>.bin` in the format of a layer file. While the
+/// next layer is generated, its partial state can be saved to `progress-<k>.bin`. All files are
+/// written to a temporary file first and then renamed, so an interrupted write leaves the previous
+/// checkpoint intact.
+pub struct Checkpoint {
+    dir: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    NotEmpty,
+    NoConfig,
+    ConfigMismatch { expected: String, found: String },
+    NoLayers,
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{}", err),
+            CheckpointError::NotEmpty => write!(f, "directory already contains a checkpoint"),
+            CheckpointError::NoConfig => write!(f, "directory does not contain a checkpoint"),
+            CheckpointError::ConfigMismatch { expected, found } => write!(
+                f,
+                "checkpoint was written with `{}`, but the options are `{}`",
+                found, expected
+            ),
+            CheckpointError::NoLayers => write!(f, "checkpoint does not contain a layer"),
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+impl Checkpoint {
+    /// Starts a new checkpoint directory for a search with the given options.
+    pub fn create(dir: &Path, config: &str) -> Result<Self, CheckpointError> {
+        fs::create_dir_all(dir)?;
+        let checkpoint = Self { dir: dir.into() };
+        if checkpoint.dir.join(CONFIG).exists() {
+            return Err(CheckpointError::NotEmpty);
+        }
+        write_atomically(&checkpoint.dir.join(CONFIG), |writer| {
+            writer.write_all(config.as_bytes())
+        })?;
+        Ok(checkpoint)
+    }
+
+    /// Opens an existing checkpoint directory, checking that it was written by a search with the
+    /// same options.
+    pub fn open(dir: &Path, config: &str) -> Result<Self, CheckpointError> {
+        let found = match fs::read_to_string(dir.join(CONFIG)) {
+            Ok(found) => found,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(CheckpointError::NoConfig)
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let found = found.trim_end().to_string();
+        if found != config {
+            return Err(CheckpointError::ConfigMismatch {
+                expected: config.into(),
+                found,
+            });
+        }
+        Ok(Self { dir: dir.into() })
+    }
+
+    fn layer_path(&self, layer: usize) -> PathBuf {
+        self.dir.join(format!("checkpoint-{}.bin", layer))
+    }
+
+    fn provenance_path(&self, layer: usize) -> PathBuf {
+        self.dir.join(format!("provenance-{}.bin", layer))
+    }
+
+    fn progress_path(&self, layer: usize) -> PathBuf {
+        self.dir.join(format!("progress-{}.bin", layer))
+    }
+
+    /// Saves a finished layer and removes the previous one.
+    pub fn save_layer<'a, V: Value>(
+        &self,
+        layer: usize,
+        channels: usize,
+        output_sets: impl ExactSizeIterator<Item = &'a OutputSet<V>>,
+        provenances: &[Provenance],
+    ) -> io::Result<()> {
+        assert_eq!(output_sets.len(), provenances.len());
+
+        write_atomically(&self.provenance_path(layer), |writer| {
+            write_provenances(writer, provenances)
+        })?;
+        write_atomically(&self.layer_path(layer), |writer| {
+            write_layer(writer, layer, channels, output_sets).map(|_| ())
+        })?;
+
+        let mut stale = vec![self.progress_path(layer)];
+        if layer > 0 {
+            stale.push(self.layer_path(layer - 1));
+        }
+        for path in stale {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the state of generating a layer from the newest saved layer.
+    ///
+    /// `provenance` selects the provenance from the item of an output set.
+    pub fn save_progress<T, V: Value>(
+        &self,
+        layer: usize,
+        state: &IncrementalState<T, V, (usize, OutputSet<V>)>,
+        provenance: impl Fn(&T) -> &Provenance,
+    ) -> io::Result<()> {
+        write_atomically(&self.progress_path(layer), |writer| {
+            let mut writer = ChecksumWriter {
+                inner: writer,
+                checksum: Fnv1a::default(),
+            };
+            write_varint(&mut writer, state.chunk_size as u64)?;
+            for pairs in [&state.minimal, &state.spilled].iter() {
+                write_varint(&mut writer, pairs.len() as u64)?;
+                for pair in pairs.iter() {
+                    write_output_set(&mut writer, &pair.output_set)?;
+                    write_provenance(&mut writer, provenance(&pair.item))?;
+                }
+            }
+            write_varint(&mut writer, state.inputs.len() as u64)?;
+            for (parent, output_set) in state.inputs.iter() {
+                write_varint(&mut writer, *parent as u64)?;
+                write_output_set(&mut writer, output_set)?;
+            }
+            let checksum = writer.checksum.0;
+            writer.inner.write_all(&checksum.to_le_bytes())
+        })
+    }
+
+    /// Loads the state of generating a layer, if it was saved.
+    pub fn load_progress<V: Value>(
+        &self,
+        layer: usize,
+        channels: usize,
+    ) -> Result<Option<LayerProgress<V>>, CheckpointError> {
+        let file = match File::open(self.progress_path(layer)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut reader = ChecksumReader {
+            inner: BufReader::new(file),
+            checksum: Fnv1a::default(),
+        };
+
+        let chunk_size = read_varint(&mut reader)? as usize;
+
+        let read_pairs = |reader: &mut ChecksumReader<_>| -> io::Result<_> {
+            let len = read_varint(reader)? as usize;
+            let mut pairs = Vec::with_capacity(len.min(1 << 20));
+            for _ in 0..len {
+                let output_set = read_output_set(reader, channels)?;
+                let provenance = read_provenance(reader)?;
+                pairs.push(AbstractedPair::new(output_set, provenance));
+            }
+            Ok(pairs)
+        };
+        let minimal = read_pairs(&mut reader)?;
+        let spilled = read_pairs(&mut reader)?;
+
+        let len = read_varint(&mut reader)? as usize;
+        let mut inputs = Vec::with_capacity(len.min(1 << 20));
+        for _ in 0..len {
+            let parent = read_varint(&mut reader)? as usize;
+            inputs.push((parent, read_output_set(&mut reader, channels)?));
+        }
+
+        let expected = reader.checksum.0;
+        if read_u64(&mut reader.inner)? != expected {
+            return Err(invalid_data("progress checksum mismatch").into());
+        }
+
+        Ok(Some(IncrementalState {
+            minimal,
+            spilled,
+            inputs,
+            chunk_size,
+        }))
+    }
+
+    /// Index of the newest saved layer.
+    pub fn latest_layer(&self) -> io::Result<Option<usize>> {
+        let mut latest = None;
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let layer = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("checkpoint-"))
+                .and_then(|name| name.strip_suffix(".bin"))
+                .and_then(|layer| layer.parse::<usize>().ok());
+            latest = latest.max(layer);
+        }
+        Ok(latest)
+    }
+
+    /// Loads the newest saved layer.
+    ///
+    /// Returns the index of the layer, its output sets and the provenance of all layers up to and
+    /// including it.
+    #[allow(clippy::type_complexity)]
+    pub fn load_latest<V: Value>(
+        &self,
+    ) -> Result<(usize, Vec<OutputSet<V>>, Vec<Vec<Provenance>>), CheckpointError> {
+        let layer = self.latest_layer()?.ok_or(CheckpointError::NoLayers)?;
+
+        let (header, output_sets) =
+            read_layer::<_, V>(BufReader::new(File::open(self.layer_path(layer))?))?;
+        if header.layer != layer {
+            return Err(invalid_data("unexpected layer index").into());
+        }
+
+        let mut history = vec![];
+        for index in 0..=layer {
+            let reader = BufReader::new(File::open(self.provenance_path(index))?);
+            history.push(read_provenances(reader)?);
+        }
+
+        if history[layer].len() != output_sets.len() {
+            return Err(invalid_data("provenance does not match the layer").into());
+        }
+
+        Ok((layer, output_sets, history))
+    }
+}
+
+/// Writes a file by writing a temporary file next to it and renaming that once it is synced to
+/// disk.
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    write(&mut writer)?;
+    writer.into_inner()?.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("checkpoint-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn save_and_resume() {
+        let dir = temp_dir("resume");
+        let config = "width=6 target=sort";
+
+        let checkpoint = Checkpoint::create(&dir, config).unwrap();
+        assert!(matches!(
+            checkpoint.load_latest::<u16>(),
+            Err(CheckpointError::NoLayers)
+        ));
+
+        let all_values = OutputSet::<u16>::all_values(6);
+        let layers = [
+            vec![all_values.clone()],
+            vec![all_values.apply_comparator(0, 1)],
+            vec![
+                all_values.apply_comparator(0, 1).apply_comparator(2, 3),
+                all_values.apply_comparator(0, 1).apply_comparator(1, 2),
+            ],
+        ];
+        let provenances = [
+            vec![Provenance::default()],
+            vec![Provenance::new(0, &[(0, 1)], &[])],
+            vec![
+                Provenance::new(0, &[(2, 3)], &[0, 1, 2, 3, 4, 5]),
+                Provenance::new(0, &[(1, 2)], &[1, 0, 2, 3, 5, 4]),
+            ],
+        ];
+
+        for (index, (layer, provenances)) in layers.iter().zip(provenances.iter()).enumerate() {
+            checkpoint
+                .save_layer(index, 6, layer.iter(), provenances)
+                .unwrap();
+        }
+
+        // A write interrupted before the rename leaves only a temporary file behind.
+        fs::write(dir.join("checkpoint-3.tmp"), b"partial").unwrap();
+
+        assert!(matches!(
+            Checkpoint::create(&dir, config),
+            Err(CheckpointError::NotEmpty)
+        ));
+        assert!(matches!(
+            Checkpoint::open(&dir, "width=7 target=sort"),
+            Err(CheckpointError::ConfigMismatch { .. })
+        ));
+
+        let resumed = Checkpoint::open(&dir, config).unwrap();
+        assert_eq!(resumed.latest_layer().unwrap(), Some(2));
+        let (layer, output_sets, history) = resumed.load_latest::<u16>().unwrap();
+        assert_eq!(layer, 2);
+        assert_eq!(output_sets, layers[2]);
+        assert_eq!(history.len(), 3);
+        for (loaded, saved) in history.iter().zip(provenances.iter()) {
+            assert_eq!(loaded.len(), saved.len());
+            for (loaded, saved) in loaded.iter().zip(saved.iter()) {
+                assert_eq!(loaded.parent, saved.parent);
+                assert_eq!(loaded.comparators, saved.comparators);
+                assert_eq!(loaded.perm, saved.perm);
+            }
+        }
+        assert!(!dir.join("checkpoint-1.bin").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(
+            Checkpoint::open(&dir, config),
+            Err(CheckpointError::NoConfig)
+        ));
+    }
+
+    #[test]
+    fn save_and_load_progress() {
+        let dir = temp_dir("progress");
+        let checkpoint = Checkpoint::create(&dir, "width=5").unwrap();
+
+        let all_values = OutputSet::<u16>::all_values(5);
+        let state = IncrementalState {
+            minimal: vec![AbstractedPair::new(
+                all_values.apply_comparator(0, 1),
+                (Provenance::new(0, &[(0, 1)], &[1, 0, 2, 3, 4]), ()),
+            )],
+            spilled: vec![
+                AbstractedPair::new(
+                    all_values.apply_comparator(1, 2),
+                    (Provenance::new(0, &[(1, 2)], &[]), ()),
+                ),
+                AbstractedPair::new(
+                    all_values.apply_comparator(3, 4),
+                    (Provenance::new(0, &[(3, 4)], &[]), ()),
+                ),
+            ],
+            inputs: vec![(1, all_values.clone()), (2, all_values.reflect())],
+            chunk_size: 4096,
+        };
+
+        assert!(checkpoint.load_progress::<u16>(1, 5).unwrap().is_none());
+
+        checkpoint
+            .save_progress(1, &state, |item: &(Provenance, ())| &item.0)
+            .unwrap();
+
+        let loaded = checkpoint.load_progress::<u16>(1, 5).unwrap().unwrap();
+        assert_eq!(loaded.chunk_size, state.chunk_size);
+        assert_eq!(loaded.inputs, state.inputs);
+        for (loaded, saved) in [
+            (&loaded.minimal, &state.minimal),
+            (&loaded.spilled, &state.spilled),
+        ]
+        .iter()
+        {
+            assert_eq!(loaded.len(), saved.len());
+            for (loaded, saved) in loaded.iter().zip(saved.iter()) {
+                assert_eq!(loaded.output_set, saved.output_set);
+                assert_eq!(loaded.item.comparators, saved.item.0.comparators);
+                assert_eq!(loaded.item.perm, saved.item.0.perm);
+            }
+        }
+
+        checkpoint
+            .save_layer(1, 5, [all_values].iter(), &[Provenance::default()])
+            .unwrap();
+        assert!(checkpoint.load_progress::<u16>(1, 5).unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
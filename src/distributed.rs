@@ -0,0 +1,470 @@
+//! Generating a layer with worker processes connected over TCP.
+//!
+//! A coordinator splits the inputs of a layer into shards and hands them to the connected workers,
+//! each working on one shard at a time. A worker generates the successors of its shard, prunes them
+//! against an index of its own and sends back the minimal ones. The coordinator merges the results
+//! of all shards into the next layer.
+//!
+//! Workers connect with a handshake containing the options of the search, which have to match
+//! those of the coordinator. All further messages consist of a tag byte and a LEB128 varint count
+//! followed by the items, as written by [`StoreItem`].
+use std::{
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    layer_file::{invalid_data, read_u32, read_varint, write_varint},
+    layer_store::StoreItem,
+    subsume_index::{
+        incremental_minimal_elements, AbstractedPair, IncrementalState, Node, SubsumeIndexItem,
+        Symmetries,
+    },
+    value::Value,
+};
+
+const MAGIC: &[u8; 8] = b"SNOWORKR";
+
+const VERSION: u32 = 1;
+
+const ACCEPTED: u8 = 1;
+const REJECTED: u8 = 0;
+
+const SHARD: u8 = 1;
+const DONE: u8 = 0;
+
+/// Number of shards per worker, so that workers finishing early can take over more shards.
+const SHARDS_PER_WORKER: usize = 16;
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+}
+
+fn write_string<W: Write>(writer: &mut W, string: &str) -> io::Result<()> {
+    write_varint(writer, string.len() as u64)?;
+    writer.write_all(string.as_bytes())
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = read_varint(reader)? as usize;
+    if len > 1 << 16 {
+        return Err(invalid_data("string too long"));
+    }
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid_data("string is not valid UTF-8"))
+}
+
+/// Splits the inputs into shards of `shard_len` inputs each, except for the last one.
+fn split_shards<In>(inputs: Vec<In>, shard_len: usize) -> Vec<Vec<In>> {
+    let mut shards = vec![];
+    let mut inputs = inputs.into_iter();
+    loop {
+        let shard = inputs.by_ref().take(shard_len).collect::<Vec<_>>();
+        if shard.is_empty() {
+            return shards;
+        }
+        shards.push(shard);
+    }
+}
+
+fn write_items<W: Write, T: StoreItem>(writer: &mut W, items: &[T]) -> io::Result<()> {
+    write_varint(writer, items.len() as u64)?;
+    for item in items {
+        item.write_item(writer)?;
+    }
+    Ok(())
+}
+
+fn read_items<R: Read, T: StoreItem>(reader: &mut R, channels: usize) -> io::Result<Vec<T>> {
+    let len = read_varint(reader)? as usize;
+    let mut items = Vec::with_capacity(len.min(1 << 20));
+    for _ in 0..len {
+        items.push(T::read_item(reader, channels)?);
+    }
+    Ok(items)
+}
+
+/// Hands out the shards of each layer to the connected workers.
+pub struct Coordinator {
+    config: String,
+    channels: usize,
+    workers: Vec<Connection>,
+}
+
+impl Coordinator {
+    /// Creates a coordinator for a search with the given options, without any workers.
+    pub fn new(config: &str, channels: usize) -> Self {
+        Self {
+            config: config.to_owned(),
+            channels,
+            workers: vec![],
+        }
+    }
+
+    /// Number of connected workers.
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Waits for the next worker to connect to `listener`.
+    ///
+    /// A worker started with different options is rejected and an error is returned.
+    pub fn accept(&mut self, listener: &TcpListener) -> io::Result<SocketAddr> {
+        let (stream, addr) = listener.accept()?;
+        let mut connection = Connection::new(stream)?;
+
+        let mut magic = [0; 8];
+        connection.reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a worker"));
+        }
+        if read_u32(&mut connection.reader)? != VERSION {
+            return Err(invalid_data("unsupported worker version"));
+        }
+
+        let config = read_string(&mut connection.reader)?;
+        if config != self.config {
+            connection.writer.write_all(&[REJECTED])?;
+            write_string(&mut connection.writer, &self.config)?;
+            connection.writer.flush()?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "worker {} runs with `{}`, but the options are `{}`",
+                    addr, config, self.config
+                ),
+            ));
+        }
+
+        connection.writer.write_all(&[ACCEPTED])?;
+        connection.writer.flush()?;
+
+        self.workers.push(connection);
+        Ok(addr)
+    }
+
+    /// Generates a layer from a saved state using the connected workers.
+    ///
+    /// The remaining inputs are split into shards, and the minimal elements returned for each
+    /// shard are merged with the minimal and spilled output sets of the state. `on_shard` is
+    /// called with the number of inputs of each finished shard.
+    ///
+    /// As for [`resume_minimal_elements`](crate::subsume_index::resume_minimal_elements), `save`
+    /// is called with the current state after each interval, and once `stop` is set the state is
+    /// returned as an error. In both cases no further shards are handed out until the shards
+    /// already sent to the workers are finished.
+    #[allow(clippy::type_complexity)]
+    pub fn minimal_elements<T, V, In, S>(
+        &mut self,
+        state: IncrementalState<T, V, In>,
+        symmetries: &Symmetries,
+        on_shard: impl Fn(usize) + Sync,
+        mut save: Option<(Duration, S)>,
+        stop: &AtomicBool,
+    ) -> io::Result<Result<Vec<AbstractedPair<T, V>>, IncrementalState<T, V, In>>>
+    where
+        T: SubsumeIndexItem + StoreItem,
+        V: Value,
+        In: Send + StoreItem,
+        S: FnMut(&IncrementalState<T, V, In>),
+    {
+        assert!(!self.workers.is_empty());
+
+        let IncrementalState {
+            mut minimal,
+            spilled,
+            inputs,
+            chunk_size,
+        } = state;
+
+        let shard_count = self.workers.len() * SHARDS_PER_WORKER;
+        let shard_len = inputs.len().div_ceil(shard_count).max(1);
+
+        let mut shards = split_shards(inputs, shard_len);
+
+        minimal.extend(spilled);
+
+        let channels = self.channels;
+
+        let mut deadline = save
+            .as_ref()
+            .map(|(interval, _)| Instant::now() + *interval);
+
+        loop {
+            let is_due = || {
+                stop.load(Ordering::Relaxed)
+                    || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            };
+
+            let remaining = Mutex::new(shards);
+            let results = Mutex::new(minimal);
+
+            thread::scope(|scope| {
+                let (remaining, results, on_shard, is_due) =
+                    (&remaining, &results, &on_shard, &is_due);
+                let handles = self
+                    .workers
+                    .iter_mut()
+                    .map(|worker| {
+                        scope.spawn(move || -> io::Result<()> {
+                            // Each worker finishes at least one shard between two saves.
+                            loop {
+                                if stop.load(Ordering::Relaxed) {
+                                    return Ok(());
+                                }
+                                let shard = match remaining.lock().pop() {
+                                    Some(shard) => shard,
+                                    None => return Ok(()),
+                                };
+
+                                worker.writer.write_all(&[SHARD])?;
+                                write_items(&mut worker.writer, &shard)?;
+                                worker.writer.flush()?;
+
+                                let pairs = read_items(&mut worker.reader, channels)?;
+                                results.lock().extend(pairs);
+                                on_shard(shard.len());
+
+                                if is_due() {
+                                    return Ok(());
+                                }
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                handles
+                    .into_iter()
+                    .try_for_each(|handle| handle.join().unwrap())
+            })?;
+
+            shards = remaining.into_inner();
+            minimal = results.into_inner();
+            if !minimal.is_empty() {
+                minimal = Node::new(minimal).minimal_elements(symmetries);
+            }
+
+            if shards.is_empty() {
+                return Ok(Ok(minimal));
+            }
+
+            let state = IncrementalState {
+                minimal,
+                spilled: vec![],
+                inputs: shards.into_iter().flatten().collect(),
+                chunk_size,
+            };
+
+            if stop.load(Ordering::Relaxed) {
+                return Ok(Err(state));
+            }
+
+            if let Some((interval, save)) = &mut save {
+                save(&state);
+                deadline = Some(Instant::now() + *interval);
+            }
+
+            minimal = state.minimal;
+            shards = split_shards(state.inputs, shard_len);
+        }
+    }
+
+    /// Tells all workers that the search is done.
+    pub fn finish(mut self) -> io::Result<()> {
+        for worker in self.workers.iter_mut() {
+            worker.writer.write_all(&[DONE])?;
+            worker.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Connects to a coordinator and generates the shards it sends until it is done.
+///
+/// `generator` returns the successors of an input, as for
+/// [`incremental_minimal_elements`]. `on_shard` is called with the number of inputs of each
+/// finished shard.
+pub fn run_worker<T, V, In, G>(
+    stream: TcpStream,
+    config: &str,
+    channels: usize,
+    symmetries: &Symmetries,
+    generator: G,
+    mut on_shard: impl FnMut(usize),
+) -> io::Result<()>
+where
+    T: SubsumeIndexItem + StoreItem,
+    V: Value,
+    G: Sync + Fn(In) -> Vec<AbstractedPair<T, V>>,
+    In: Send + StoreItem,
+{
+    let mut connection = Connection::new(stream)?;
+
+    connection.writer.write_all(MAGIC)?;
+    connection.writer.write_all(&VERSION.to_le_bytes())?;
+    write_string(&mut connection.writer, config)?;
+    connection.writer.flush()?;
+
+    let mut status = [0];
+    connection.reader.read_exact(&mut status)?;
+    match status[0] {
+        ACCEPTED => (),
+        REJECTED => {
+            let expected = read_string(&mut connection.reader)?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "coordinator runs with `{}`, but the options are `{}`",
+                    expected, config
+                ),
+            ));
+        }
+        _ => return Err(invalid_data("unexpected handshake response")),
+    }
+
+    loop {
+        let mut tag = [0];
+        connection.reader.read_exact(&mut tag)?;
+        match tag[0] {
+            SHARD => {
+                let inputs = read_items::<_, In>(&mut connection.reader, channels)?;
+                let len = inputs.len();
+                let minimal = incremental_minimal_elements(inputs, symmetries, &generator);
+                write_items(&mut connection.writer, &minimal)?;
+                connection.writer.flush()?;
+                on_shard(len);
+            }
+            DONE => return Ok(()),
+            _ => return Err(invalid_data("unexpected message")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::output_set::OutputSet;
+
+    fn generator(channels: usize) -> impl Sync + Fn(OutputSet) -> Vec<AbstractedPair<(), u16>> {
+        move |output_set: OutputSet| {
+            (0..channels)
+                .flat_map(|j| (0..j).map(move |i| (i, j)))
+                .map(|(i, j)| {
+                    let mut next = output_set.apply_comparator(i, j);
+                    next.order_channels_by_weight();
+                    AbstractedPair::new(next, ())
+                })
+                .collect::<Vec<_>>()
+        }
+    }
+
+    #[test]
+    fn coordinate_workers() {
+        crate::logging::setup();
+
+        let channels = 6;
+        let symmetries = Symmetries::default();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let workers = (0..3)
+            .map(|index| {
+                thread::spawn(move || {
+                    let config = if index == 0 { "width=5" } else { "width=6" };
+                    let stream = TcpStream::connect(addr).unwrap();
+                    run_worker(
+                        stream,
+                        config,
+                        channels,
+                        &Symmetries::default(),
+                        generator(channels),
+                        |_| {},
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut coordinator = Coordinator::new("width=6", channels);
+        let rejected = (0..3)
+            .filter(|_| coordinator.accept(&listener).is_err())
+            .count();
+        assert_eq!(rejected, 1);
+        assert_eq!(coordinator.workers(), 2);
+
+        let mut layer = vec![OutputSet::<u16>::all_values(channels)];
+        for _ in 0..4 {
+            let expected =
+                incremental_minimal_elements(layer.clone(), &symmetries, generator(channels));
+
+            // Stopping before any shard is handed out keeps all inputs.
+            let stopped = coordinator
+                .minimal_elements(
+                    IncrementalState::new(layer.clone()),
+                    &symmetries,
+                    |_| {},
+                    None::<(Duration, fn(&_))>,
+                    &AtomicBool::new(true),
+                )
+                .unwrap()
+                .err()
+                .unwrap();
+            assert_eq!(stopped.inputs.len(), layer.len());
+
+            // Saving after every round of shards does not change the result.
+            let finished = Mutex::new(0);
+            let mut saves = 0;
+            let minimal = coordinator
+                .minimal_elements(
+                    stopped,
+                    &symmetries,
+                    |len| *finished.lock() += len,
+                    Some((Duration::from_secs(0), |_: &IncrementalState<_, _, _>| {
+                        saves += 1
+                    })),
+                    &AtomicBool::new(false),
+                )
+                .unwrap()
+                .ok()
+                .unwrap();
+            assert_eq!(finished.into_inner(), layer.len());
+            assert!(layer.len() <= 2 || saves > 0);
+
+            assert_eq!(minimal.len(), expected.len());
+            let index = Node::new(expected);
+            for pair in minimal.iter() {
+                assert!(index
+                    .combine_with_subsuming(pair.clone(), &symmetries)
+                    .is_ok());
+            }
+
+            layer = minimal.into_iter().map(|pair| pair.output_set).collect();
+        }
+
+        coordinator.finish().unwrap();
+
+        let results = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 2);
+    }
+}
@@ -0,0 +1,211 @@
+use std::{fmt, fs, io, path::PathBuf};
+
+use crate::{
+    output_set::{OutputSet, ParseOutputSetError},
+    value::Value,
+};
+
+/// Specification of the set of inputs a network has to handle.
+///
+/// Parsed from `all`, `blocks:<a>,<b>,...` (each block of consecutive channels is presorted),
+/// `merge:<m>,<n>` (two presorted blocks), `inversions:<t>` (at most `t` pairs of channels out
+/// of order) or `file:<path>` (an output set in its text format).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InputSpec {
+    All,
+    Blocks(Vec<usize>),
+    Inversions(usize),
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum InputSetError {
+    WrongWidth { expected: usize, found: usize },
+    TooManyChannels { channels: usize },
+    Io(io::Error),
+    Parse(ParseOutputSetError),
+}
+
+impl fmt::Display for InputSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputSetError::WrongWidth { expected, found } => {
+                write!(f, "input set has {} channels, expected {}", found, expected)
+            }
+            InputSetError::TooManyChannels { channels } => write!(
+                f,
+                "{} channels are not supported by this value type",
+                channels
+            ),
+            InputSetError::Io(err) => write!(f, "{}", err),
+            InputSetError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for InputSetError {}
+
+impl InputSpec {
+    /// Builds the input set for a network with the given number of channels.
+    pub fn output_set<V: Value>(&self, channels: usize) -> Result<OutputSet<V>, InputSetError> {
+        if channels > V::MAX_CHANNELS {
+            return Err(InputSetError::TooManyChannels { channels });
+        }
+
+        let output_set = match self {
+            InputSpec::All => OutputSet::all_values(channels),
+            InputSpec::Blocks(block_sizes) => {
+                let found = block_sizes.iter().sum();
+                if found != channels {
+                    return Err(InputSetError::WrongWidth {
+                        expected: channels,
+                        found,
+                    });
+                }
+                OutputSet::presorted_blocks(block_sizes)
+            }
+            InputSpec::Inversions(max_inversions) => {
+                OutputSet::bounded_inversions(channels, *max_inversions)
+            }
+            InputSpec::File(path) => fs::read_to_string(path)
+                .map_err(InputSetError::Io)?
+                .parse()
+                .map_err(InputSetError::Parse)?,
+        };
+
+        if output_set.channels() != channels {
+            return Err(InputSetError::WrongWidth {
+                expected: channels,
+                found: output_set.channels(),
+            });
+        }
+
+        Ok(output_set)
+    }
+}
+
+/// Error returned when parsing an input set specification fails.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseInputSpecError;
+
+impl fmt::Display for ParseInputSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected `all`, `blocks:<a>,<b>,...`, `merge:<m>,<n>`, `inversions:<t>` or \
+             `file:<path>`"
+        )
+    }
+}
+
+impl std::error::Error for ParseInputSpecError {}
+
+impl fmt::Display for InputSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputSpec::All => write!(f, "all"),
+            InputSpec::Blocks(block_sizes) => {
+                write!(f, "blocks:")?;
+                for (index, size) in block_sizes.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", size)?;
+                }
+                Ok(())
+            }
+            InputSpec::Inversions(max_inversions) => write!(f, "inversions:{}", max_inversions),
+            InputSpec::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+impl std::str::FromStr for InputSpec {
+    type Err = ParseInputSpecError;
+
+    fn from_str(s: &str) -> Result<Self, ParseInputSpecError> {
+        if s == "all" {
+            return Ok(InputSpec::All);
+        }
+
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().ok_or(ParseInputSpecError)?;
+        let arg = parts.next().ok_or(ParseInputSpecError)?;
+
+        let sizes = || {
+            arg.split(',')
+                .map(|size| size.parse::<usize>().ok().filter(|&size| size > 0))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(ParseInputSpecError)
+        };
+
+        match name {
+            "blocks" => Ok(InputSpec::Blocks(sizes()?)),
+            "merge" => {
+                let sizes = sizes()?;
+                if sizes.len() != 2 {
+                    return Err(ParseInputSpecError);
+                }
+                Ok(InputSpec::Blocks(sizes))
+            }
+            "inversions" => arg
+                .parse()
+                .map(InputSpec::Inversions)
+                .map_err(|_| ParseInputSpecError),
+            "file" if !arg.is_empty() => Ok(InputSpec::File(arg.into())),
+            _ => Err(ParseInputSpecError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_input_specs() {
+        let parse = |text: &str| text.parse::<InputSpec>();
+
+        assert_eq!(parse("all"), Ok(InputSpec::All));
+        assert_eq!(parse("merge:4,3"), Ok(InputSpec::Blocks(vec![4, 3])));
+        assert_eq!(parse("blocks:2,2,2"), Ok(InputSpec::Blocks(vec![2, 2, 2])));
+        assert_eq!(parse("inversions:3"), Ok(InputSpec::Inversions(3)));
+        assert_eq!(
+            parse("file:some/path.txt"),
+            Ok(InputSpec::File("some/path.txt".into()))
+        );
+
+        for spec in ["blocks:2,2,2", "inversions:3", "file:x"].iter() {
+            assert_eq!(parse(spec).unwrap().to_string(), *spec);
+        }
+
+        for spec in ["", "none", "merge:4", "merge:1,2,3", "blocks:2,0", "file:"].iter() {
+            assert_eq!(parse(spec), Err(ParseInputSpecError));
+        }
+    }
+
+    #[test]
+    fn build_input_sets() {
+        let merge = InputSpec::Blocks(vec![4, 3]).output_set::<u16>(7).unwrap();
+        assert_eq!(merge, OutputSet::presorted_blocks(&[4, 3]));
+
+        assert!(matches!(
+            InputSpec::Blocks(vec![4, 3]).output_set::<u16>(8),
+            Err(InputSetError::WrongWidth {
+                expected: 8,
+                found: 7
+            })
+        ));
+
+        assert!(matches!(
+            InputSpec::All.output_set::<u16>(16),
+            Err(InputSetError::TooManyChannels { channels: 16 })
+        ));
+
+        let path = std::env::temp_dir().join(format!("input-set-{}.txt", std::process::id()));
+        fs::write(&path, merge.to_string()).unwrap();
+        let from_file = InputSpec::File(path.clone()).output_set::<u32>(7);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(from_file.unwrap(), OutputSet::presorted_blocks(&[4, 3]));
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+
+use crate::{output_set::OutputSet, value::Value};
+
+const MAGIC: &[u8; 8] = b"SNOLAYER";
+
+const VERSION: u32 = 1;
+
+/// Header of a layer file.
+///
+/// A layer file starts with the magic bytes `SNOLAYER`, followed by the format version, the number
+/// of channels, the layer index and the number of output sets, stored as little endian `u32`,
+/// `u32`, `u64` and `u64`. Each output set is stored as the number of values followed by the
+/// differences between consecutive values, all as LEB128 varints. The file ends with a 64-bit
+/// FNV-1a checksum of all preceding bytes, including the header.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LayerHeader {
+    pub channels: usize,
+    pub layer: usize,
+    pub len: usize,
+}
+
+/// Writes a layer file one output set at a time.
+pub struct LayerWriter<W: Write> {
+    writer: ChecksumWriter<W>,
+    header: LayerHeader,
+    written: usize,
+}
+
+impl<W: Write> LayerWriter<W> {
+    pub fn new(writer: W, header: LayerHeader) -> io::Result<Self> {
+        let mut writer = ChecksumWriter {
+            inner: writer,
+            checksum: Fnv1a::default(),
+        };
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(header.channels as u32).to_le_bytes())?;
+        writer.write_all(&(header.layer as u64).to_le_bytes())?;
+        writer.write_all(&(header.len as u64).to_le_bytes())?;
+        Ok(Self {
+            writer,
+            header,
+            written: 0,
+        })
+    }
+
+    pub fn write<V: Value>(&mut self, output_set: &OutputSet<V>) -> io::Result<()> {
+        assert_eq!(output_set.channels(), self.header.channels);
+        assert!(self.written < self.header.len);
+        self.written += 1;
+
+        write_output_set(&mut self.writer, output_set)
+    }
+
+    /// Writes the checksum and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        assert_eq!(self.written, self.header.len);
+        let checksum = self.writer.checksum.0;
+        self.writer.inner.write_all(&checksum.to_le_bytes())?;
+        self.writer.inner.flush()?;
+        Ok(self.writer.inner)
+    }
+}
+
+/// Reads a layer file one output set at a time.
+///
+/// The checksum is verified after the last output set has been read, so an error can still be
+/// returned at that point.
+pub struct LayerReader<R: Read, V: Value = u16> {
+    reader: ChecksumReader<R>,
+    header: LayerHeader,
+    read: usize,
+    finished: bool,
+    value: PhantomData<V>,
+}
+
+impl<R: Read, V: Value> LayerReader<R, V> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = ChecksumReader {
+            inner: reader,
+            checksum: Fnv1a::default(),
+        };
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a layer file"));
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(invalid_data("unsupported layer file version"));
+        }
+
+        let channels = read_u32(&mut reader)? as usize;
+        let layer = read_u64(&mut reader)? as usize;
+        let len = read_u64(&mut reader)? as usize;
+
+        if channels > V::MAX_CHANNELS {
+            return Err(invalid_data("too many channels for the value type"));
+        }
+
+        Ok(Self {
+            reader,
+            header: LayerHeader {
+                channels,
+                layer,
+                len,
+            },
+            read: 0,
+            finished: false,
+            value: PhantomData,
+        })
+    }
+
+    pub fn header(&self) -> &LayerHeader {
+        &self.header
+    }
+
+    fn verify_checksum(&mut self) -> io::Result<()> {
+        let expected = self.reader.checksum.0;
+        if read_u64(&mut self.reader.inner)? != expected {
+            return Err(invalid_data("layer file checksum mismatch"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read, V: Value> Iterator for LayerReader<R, V> {
+    type Item = io::Result<OutputSet<V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        if self.read == self.header.len {
+            self.finished = true;
+            return self.verify_checksum().err().map(Err);
+        }
+        self.read += 1;
+        let result = read_output_set(&mut self.reader, self.header.channels);
+        if result.is_err() {
+            self.finished = true;
+        }
+        Some(result)
+    }
+}
+
+/// Writes a complete layer file.
+pub fn write_layer<'a, W: Write, V: Value>(
+    writer: W,
+    layer: usize,
+    channels: usize,
+    output_sets: impl ExactSizeIterator<Item = &'a OutputSet<V>>,
+) -> io::Result<W> {
+    let header = LayerHeader {
+        channels,
+        layer,
+        len: output_sets.len(),
+    };
+    let mut writer = LayerWriter::new(writer, header)?;
+    for output_set in output_sets {
+        writer.write(output_set)?;
+    }
+    writer.finish()
+}
+
+/// Reads a complete layer file.
+pub fn read_layer<R: Read, V: Value>(reader: R) -> io::Result<(LayerHeader, Vec<OutputSet<V>>)> {
+    let reader = LayerReader::new(reader)?;
+    let header = *reader.header();
+    let output_sets = reader.collect::<io::Result<Vec<_>>>()?;
+    Ok((header, output_sets))
+}
+
+/// Writes the number of values of an output set followed by the differences between consecutive
+/// values, as LEB128 varints.
+pub(crate) fn write_output_set<W: Write, V: Value>(
+    writer: &mut W,
+    output_set: &OutputSet<V>,
+) -> io::Result<()> {
+    write_varint(writer, output_set.values().len() as u64)?;
+    let mut prev = 0;
+    for &value in output_set.values() {
+        let value = value.to_usize() as u64;
+        write_varint(writer, value - prev)?;
+        prev = value;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_output_set<R: Read, V: Value>(
+    reader: &mut R,
+    channels: usize,
+) -> io::Result<OutputSet<V>> {
+    let len = read_varint(reader)? as usize;
+    if len > 1 << channels {
+        return Err(invalid_data("too many values in output set"));
+    }
+
+    let mut values = Vec::with_capacity(len);
+    let mut value = 0u64;
+    for index in 0..len {
+        let delta = read_varint(reader)?;
+        if index > 0 && delta == 0 {
+            return Err(invalid_data("values not strictly increasing"));
+        }
+        value = value
+            .checked_add(delta)
+            .filter(|&value| value < 1 << channels)
+            .ok_or_else(|| invalid_data("value out of range"))?;
+        values.push(V::from_usize(value as usize));
+    }
+
+    Ok(OutputSet::from_values(channels, values))
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+    writer.write_all(&bytes[..len])
+}
+
+pub(crate) fn read_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("varint too long"))
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Fnv1a(pub(crate) u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+pub(crate) struct ChecksumWriter<W> {
+    pub(crate) inner: W,
+    pub(crate) checksum: Fnv1a,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.checksum.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub(crate) struct ChecksumReader<R> {
+    pub(crate) inner: R,
+    pub(crate) checksum: Fnv1a,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.checksum.update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn some_output_sets() -> Vec<OutputSet> {
+        let all_values = OutputSet::all_values(7);
+        let mut output_sets = vec![all_values.clone()];
+        for j in 0..7 {
+            for i in 0..j {
+                output_sets.push(all_values.apply_comparator(i, j));
+            }
+        }
+        output_sets.push(OutputSet::from_values(7, vec![]));
+        output_sets
+    }
+
+    #[test]
+    fn round_trip() {
+        let output_sets = some_output_sets();
+
+        let bytes = write_layer(vec![], 3, 7, output_sets.iter()).unwrap();
+
+        let (header, read_output_sets) = read_layer::<_, u16>(&bytes[..]).unwrap();
+
+        assert_eq!(
+            header,
+            LayerHeader {
+                channels: 7,
+                layer: 3,
+                len: output_sets.len()
+            }
+        );
+        assert_eq!(read_output_sets, output_sets);
+
+        let (_, wide_output_sets) = read_layer::<_, u32>(&bytes[..]).unwrap();
+        assert!(wide_output_sets
+            .iter()
+            .zip(output_sets.iter())
+            .all(|(wide, narrow)| wide.values().len() == narrow.values().len()));
+    }
+
+    #[test]
+    fn detects_corruption() {
+        let bytes = write_layer(vec![], 0, 7, some_output_sets().iter()).unwrap();
+
+        for pos in 0..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[pos] ^= 0x10;
+            assert!(read_layer::<_, u16>(&corrupted[..]).is_err());
+        }
+
+        assert!(read_layer::<_, u16>(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+//! Disk-backed storage for the output sets waiting to be added to a layer.
+//!
+//! While a layer is generated, only the index of minimal elements has to be kept in memory. The
+//! remaining inputs and the generated output sets that did not fit into the current chunk can be
+//! kept in a [`DiskQueue`], which writes them to temporary files in segments of a fixed number of
+//! items and reads them back one segment at a time. The provenance of the finished layers is only
+//! needed to reconstruct a network at the end and is kept in [`ProvenanceFiles`].
+use std::{
+    fs,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crossbeam::queue::SegQueue;
+use parking_lot::Mutex;
+
+use crate::{
+    layer_file::{read_output_set, read_varint, write_output_set, write_varint},
+    output_set::OutputSet,
+    provenance::{read_provenances, write_provenances, Provenance},
+    subsume_index::AbstractedPair,
+    value::Value,
+};
+
+/// Number of items per temporary file used by default.
+pub const DEFAULT_SEGMENT_LEN: usize = 1 << 16;
+
+/// Items that can be written to the temporary files of a [`DiskQueue`].
+pub trait StoreItem: Sized {
+    fn write_item<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+    fn read_item<R: Read>(reader: &mut R, channels: usize) -> io::Result<Self>;
+}
+
+impl StoreItem for () {
+    fn write_item<W: Write>(&self, _writer: &mut W) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn read_item<R: Read>(_reader: &mut R, _channels: usize) -> io::Result<Self> {
+        Ok(())
+    }
+}
+
+impl StoreItem for usize {
+    fn write_item<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_varint(writer, *self as u64)
+    }
+
+    fn read_item<R: Read>(reader: &mut R, _channels: usize) -> io::Result<Self> {
+        Ok(read_varint(reader)? as usize)
+    }
+}
+
+impl<A: StoreItem, B: StoreItem> StoreItem for (A, B) {
+    fn write_item<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.0.write_item(writer)?;
+        self.1.write_item(writer)
+    }
+
+    fn read_item<R: Read>(reader: &mut R, channels: usize) -> io::Result<Self> {
+        Ok((
+            A::read_item(reader, channels)?,
+            B::read_item(reader, channels)?,
+        ))
+    }
+}
+
+impl<V: Value> StoreItem for OutputSet<V> {
+    fn write_item<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_output_set(writer, self)
+    }
+
+    fn read_item<R: Read>(reader: &mut R, channels: usize) -> io::Result<Self> {
+        read_output_set(reader, channels)
+    }
+}
+
+/// The abstraction is not stored but recomputed when reading.
+impl<T: StoreItem, V: Value> StoreItem for AbstractedPair<T, V> {
+    fn write_item<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_output_set(writer, &self.output_set)?;
+        self.item.write_item(writer)
+    }
+
+    fn read_item<R: Read>(reader: &mut R, channels: usize) -> io::Result<Self> {
+        let output_set = read_output_set(reader, channels)?;
+        Ok(AbstractedPair::new(
+            output_set,
+            T::read_item(reader, channels)?,
+        ))
+    }
+}
+
+/// Unordered collection of items shared by the workers generating a layer.
+pub trait WorkQueue<T>: Sync {
+    fn push(&self, item: T);
+    fn pop(&self) -> Option<T>;
+    fn is_empty(&self) -> bool;
+}
+
+impl<T: Send> WorkQueue<T> for SegQueue<T> {
+    fn push(&self, item: T) {
+        SegQueue::push(self, item)
+    }
+
+    fn pop(&self) -> Option<T> {
+        SegQueue::pop(self).ok()
+    }
+
+    fn is_empty(&self) -> bool {
+        SegQueue::is_empty(self)
+    }
+}
+
+/// Directory holding the temporary files of disk queues and provenance files.
+pub struct LayerStore {
+    dir: PathBuf,
+    channels: usize,
+    segment_len: usize,
+}
+
+impl LayerStore {
+    /// Uses `dir`, creating it if necessary, for queues of output sets with the given number of
+    /// channels, storing `segment_len` items per file.
+    pub fn new(dir: &Path, channels: usize, segment_len: usize) -> io::Result<Self> {
+        assert!(segment_len > 0);
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            channels,
+            segment_len,
+        })
+    }
+
+    /// Creates an empty queue backed by temporary files in the store's directory.
+    pub fn queue<T>(&self) -> DiskQueue<T> {
+        DiskQueue {
+            dir: self.dir.clone(),
+            name: self.unique_name("queue"),
+            channels: self.channels,
+            segment_len: self.segment_len,
+            state: Mutex::new(DiskQueueState {
+                head: vec![],
+                tail: vec![],
+                segments: vec![],
+                next_segment: 0,
+            }),
+        }
+    }
+
+    /// Creates an empty list of layer provenances backed by temporary files in the store's
+    /// directory.
+    pub fn provenance_files(&self) -> ProvenanceFiles {
+        ProvenanceFiles {
+            dir: self.dir.clone(),
+            name: self.unique_name("provenance"),
+            layers: 0,
+        }
+    }
+
+    fn unique_name(&self, kind: &str) -> String {
+        static NEXT_NAME: AtomicUsize = AtomicUsize::new(0);
+
+        format!(
+            "{}-{}-{}",
+            kind,
+            std::process::id(),
+            NEXT_NAME.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+}
+
+/// The provenance of each finished layer, stored in one temporary file per layer.
+///
+/// The files are deleted when this is dropped.
+pub struct ProvenanceFiles {
+    dir: PathBuf,
+    name: String,
+    layers: usize,
+}
+
+impl ProvenanceFiles {
+    fn layer_path(&self, layer: usize) -> PathBuf {
+        self.dir.join(format!("{}-{}.bin", self.name, layer))
+    }
+
+    /// Number of stored layers.
+    pub fn len(&self) -> usize {
+        self.layers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers == 0
+    }
+
+    /// Stores the provenance of the next layer.
+    pub fn push(&mut self, provenances: &[Provenance]) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(self.layer_path(self.layers))?);
+        write_provenances(&mut writer, provenances)?;
+        writer.flush()?;
+        self.layers += 1;
+        Ok(())
+    }
+
+    /// Reads back the provenance of a stored layer.
+    pub fn load(&self, layer: usize) -> io::Result<Vec<Provenance>> {
+        assert!(layer < self.layers);
+        read_provenances(BufReader::new(File::open(self.layer_path(layer))?))
+    }
+}
+
+impl Drop for ProvenanceFiles {
+    fn drop(&mut self) {
+        for layer in 0..self.layers {
+            let _ = fs::remove_file(self.layer_path(layer));
+        }
+    }
+}
+
+/// Queue keeping at most two segments in memory and the remaining items in temporary files.
+///
+/// Pushed items are collected in memory until a segment is full, which is then written to a new
+/// file. Items are popped from the segment read last, reading the next file once it is empty. The
+/// files are deleted after reading them and when the queue is dropped.
+///
+/// Panics if a temporary file cannot be written or read.
+pub struct DiskQueue<T> {
+    dir: PathBuf,
+    name: String,
+    channels: usize,
+    segment_len: usize,
+    state: Mutex<DiskQueueState<T>>,
+}
+
+struct DiskQueueState<T> {
+    /// Items read from a file.
+    head: Vec<T>,
+    /// Items not yet written to a file.
+    tail: Vec<T>,
+    /// Numbers of the files holding a segment.
+    segments: Vec<usize>,
+    next_segment: usize,
+}
+
+impl<T> DiskQueue<T> {
+    fn segment_path(&self, segment: usize) -> PathBuf {
+        self.dir.join(format!("{}-{}.bin", self.name, segment))
+    }
+}
+
+impl<T: StoreItem> DiskQueue<T> {
+    fn write_segment(&self, segment: usize, items: &[T]) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(self.segment_path(segment))?);
+        for item in items {
+            item.write_item(&mut writer)?;
+        }
+        writer.flush()
+    }
+
+    fn read_segment(&self, segment: usize) -> io::Result<Vec<T>> {
+        let path = self.segment_path(segment);
+        let mut reader = BufReader::new(File::open(&path)?);
+        let mut items = Vec::with_capacity(self.segment_len);
+        for _ in 0..self.segment_len {
+            items.push(T::read_item(&mut reader, self.channels)?);
+        }
+        fs::remove_file(&path)?;
+        Ok(items)
+    }
+}
+
+impl<T: StoreItem + Send> WorkQueue<T> for DiskQueue<T> {
+    fn push(&self, item: T) {
+        let (segment, items) = {
+            let mut state = self.state.lock();
+            state.tail.push(item);
+            if state.tail.len() < self.segment_len {
+                return;
+            }
+            let segment = state.next_segment;
+            state.next_segment += 1;
+            (segment, std::mem::take(&mut state.tail))
+        };
+
+        self.write_segment(segment, &items).unwrap_or_else(|err| {
+            panic!(
+                "cannot write {}: {}",
+                self.segment_path(segment).display(),
+                err
+            )
+        });
+
+        self.state.lock().segments.push(segment);
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock();
+        if let Some(item) = state.head.pop() {
+            return Some(item);
+        }
+        if let Some(segment) = state.segments.pop() {
+            state.head = self.read_segment(segment).unwrap_or_else(|err| {
+                panic!(
+                    "cannot read {}: {}",
+                    self.segment_path(segment).display(),
+                    err
+                )
+            });
+            return state.head.pop();
+        }
+        state.tail.pop()
+    }
+
+    fn is_empty(&self) -> bool {
+        let state = self.state.lock();
+        state.head.is_empty() && state.tail.is_empty() && state.segments.is_empty()
+    }
+}
+
+impl<T> Drop for DiskQueue<T> {
+    fn drop(&mut self) {
+        for segment in std::mem::take(&mut self.state.get_mut().segments) {
+            let _ = fs::remove_file(self.segment_path(segment));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        certificate::{Transform, Witnesses},
+        output_set::OutputSet,
+        provenance::Provenance,
+    };
+
+    #[test]
+    fn disk_queue() {
+        let dir = std::env::temp_dir().join(format!("sortnetopt-store-{}", std::process::id()));
+        let store = LayerStore::new(&dir, 6, 7).unwrap();
+
+        let all_values = OutputSet::<u16>::all_values(6);
+        let items = (0..6)
+            .flat_map(|j| (0..j).map(move |i| (i, j)))
+            .enumerate()
+            .map(|(index, (i, j))| {
+                let witnesses = Witnesses(vec![(index, Transform::new(i % 2 == 0, &[j, i]))]);
+                AbstractedPair::new(
+                    all_values.apply_comparator(i, j),
+                    (index, (Provenance::new(index, &[(i, j)], &[]), witnesses)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let queue = store.queue();
+        for item in items.iter() {
+            queue.push(item.clone());
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), items.len() / 7);
+
+        let mut popped = vec![];
+        while let Some(item) = queue.pop() {
+            popped.push(item);
+            if popped.len() == 3 {
+                queue.push(items[0].clone());
+            }
+        }
+        assert!(queue.is_empty());
+        assert_eq!(popped.len(), items.len() + 1);
+
+        popped.sort_by_key(|pair| pair.item.0);
+        popped.remove(0);
+        for (popped, item) in popped.iter().zip(items.iter()) {
+            assert_eq!(popped.output_set, item.output_set);
+            let (index, (provenance, witnesses)) = &popped.item;
+            let (expected_index, (expected_provenance, expected_witnesses)) = &item.item;
+            assert_eq!(index, expected_index);
+            assert_eq!(provenance.comparators, expected_provenance.comparators);
+            assert_eq!(witnesses.0, expected_witnesses.0);
+        }
+
+        for item in items.iter() {
+            queue.push(item.clone());
+        }
+        drop(queue);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn provenance_files() {
+        let dir = std::env::temp_dir().join(format!("sortnetopt-history-{}", std::process::id()));
+        let store = LayerStore::new(&dir, 6, 7).unwrap();
+
+        let layers = (0..4)
+            .map(|layer| {
+                (0..layer * 3)
+                    .map(|index| Provenance::new(index / 2, &[(index % 6, 5 - index % 6)], &[]))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut files = store.provenance_files();
+        for layer in layers.iter() {
+            files.push(layer).unwrap();
+        }
+        assert_eq!(files.len(), layers.len());
+
+        for (index, layer) in layers.iter().enumerate().rev() {
+            let loaded = files.load(index).unwrap();
+            assert_eq!(loaded.len(), layer.len());
+            for (loaded, saved) in loaded.iter().zip(layer.iter()) {
+                assert_eq!(loaded.parent, saved.parent);
+                assert_eq!(loaded.comparators, saved.comparators);
+            }
+        }
+
+        drop(files);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
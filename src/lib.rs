@@ -1,4 +1,19 @@
+pub mod automorphism;
+pub mod bit_sliced;
+pub mod bitmap_output_set;
+pub mod certificate;
+pub mod checkpoint;
+pub mod distributed;
+pub mod input_set;
+pub mod layer_file;
+pub mod layer_store;
 pub mod logging;
 pub mod matching;
+pub mod network;
 pub mod output_set;
+pub mod path_count;
+pub mod provenance;
 pub mod subsume_index;
+pub mod target;
+pub mod topology;
+pub mod value;
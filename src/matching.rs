@@ -1,17 +1,20 @@
+// The nested conditionals keep the channel check apart from the removal with side effects.
+#![allow(clippy::collapsible_if)]
+
 use std::mem::swap;
 
 use crate::output_set::CVec;
 
 #[derive(Clone)]
 pub struct Matching {
-    matches_a: CVec<u16>,
-    matches_b: CVec<u16>,
+    matches_a: CVec<u32>,
+    matches_b: CVec<u32>,
     incomplete: bool,
 }
 
 impl Matching {
     pub fn new(channels: usize) -> Matching {
-        let all = (1u16 << channels) - 1;
+        let all = (1u32 << channels) - 1;
         Matching {
             matches_a: (0..channels).map(|_| all).collect(),
             matches_b: (0..channels).map(|_| all).collect(),
@@ -110,15 +113,15 @@ impl Matching {
     pub fn swap_channels_a(&mut self, channel_a_0: usize, channel_a_1: usize) {
         self.matches_a.swap(channel_a_0, channel_a_1);
 
-        let col_b_0 = 1u16 << channel_a_0;
-        let col_b_1 = 1u16 << channel_a_1;
+        let col_b_0 = 1u32 << channel_a_0;
+        let col_b_1 = 1u32 << channel_a_1;
 
         let col_b_both = col_b_0 | col_b_1;
 
         for row_b in self.matches_b.iter_mut() {
             let exchange = *row_b & col_b_both;
             let flip = (exchange == col_b_0) | (exchange == col_b_1);
-            *row_b ^= col_b_both * (flip as u16);
+            *row_b ^= col_b_both * (flip as u32);
         }
     }
 
@@ -162,11 +165,11 @@ impl Matching {
         }
     }
 
-    pub fn matches_a(&self, channel_a: usize) -> u16 {
+    pub fn matches_a(&self, channel_a: usize) -> u32 {
         self.matches_a[channel_a]
     }
 
-    pub fn matches_b(&self, channel_b: usize) -> u16 {
+    pub fn matches_b(&self, channel_b: usize) -> u32 {
         self.matches_b[channel_b]
     }
 }
@@ -0,0 +1,596 @@
+use std::{fmt, iter::Peekable};
+
+use crate::{
+    bit_sliced::{self, BitSlice},
+    output_set::OutputSet,
+    value::Value,
+};
+
+/// A comparator network.
+///
+/// A comparator `(a, b)` acts like [`OutputSet::apply_comparator`], i.e. afterwards channel `a`
+/// holds the larger and channel `b` the smaller of the two inputs. The network is standard when
+/// `a < b` holds for all comparators and generalized otherwise.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Network {
+    channels: usize,
+    comparators: Vec<(usize, usize)>,
+}
+
+/// Why a comparator of a network is redundant at its position.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Redundancy {
+    /// The comparator never exchanges its inputs and can be removed.
+    NeverSwaps,
+    /// The comparator exchanges its inputs whenever they differ, so it acts like crossing the two
+    /// channels.
+    AlwaysSwaps,
+}
+
+/// Error returned when parsing a network fails.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ParseNetworkError {
+    InvalidSyntax { offset: usize },
+    InvalidChannel { offset: usize },
+    SameChannel { offset: usize },
+}
+
+impl fmt::Display for ParseNetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseNetworkError::InvalidSyntax { offset } => {
+                write!(f, "offset {}: expected a comparator `(a,b)`", offset)
+            }
+            ParseNetworkError::InvalidChannel { offset } => {
+                write!(f, "offset {}: invalid channel", offset)
+            }
+            ParseNetworkError::SameChannel { offset } => write!(
+                f,
+                "offset {}: comparator connects a channel to itself",
+                offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseNetworkError {}
+
+/// Writes the network as `[(a,b),(c,d),...]`.
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (index, &(a, b)) in self.comparators.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "({},{})", a, b)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Parses a network written as a list of comparators `(a,b)`.
+///
+/// Whitespace is ignored and brackets only group comparators, so both `[(0,1),(2,3)]` and the
+/// layered `[[(0,1),(2,3)],[(1,2)]]` are accepted. The number of channels is one more than the
+/// largest channel used.
+impl std::str::FromStr for Network {
+    type Err = ParseNetworkError;
+
+    fn from_str(s: &str) -> Result<Self, ParseNetworkError> {
+        let mut tokens = s
+            .char_indices()
+            .filter(|(_, c)| !c.is_whitespace())
+            .peekable();
+
+        let mut comparators = vec![];
+
+        while let Some((offset, c)) = tokens.next() {
+            match c {
+                '[' | ']' | ',' => continue,
+                '(' => (),
+                _ => return Err(ParseNetworkError::InvalidSyntax { offset }),
+            }
+
+            let a = parse_channel(&mut tokens, s.len())?;
+
+            match tokens.next() {
+                Some((_, ',')) => (),
+                other => {
+                    return Err(ParseNetworkError::InvalidSyntax {
+                        offset: other.map_or(s.len(), |(pos, _)| pos),
+                    })
+                }
+            }
+
+            let b = parse_channel(&mut tokens, s.len())?;
+
+            match tokens.next() {
+                Some((_, ')')) => (),
+                other => {
+                    return Err(ParseNetworkError::InvalidSyntax {
+                        offset: other.map_or(s.len(), |(pos, _)| pos),
+                    })
+                }
+            }
+
+            if a == b {
+                return Err(ParseNetworkError::SameChannel { offset });
+            }
+
+            comparators.push((a, b));
+        }
+
+        let channels = comparators
+            .iter()
+            .map(|&(a, b)| a.max(b) + 1)
+            .max()
+            .unwrap_or(0);
+
+        Ok(Self::new(channels, comparators))
+    }
+}
+
+fn parse_channel(
+    tokens: &mut Peekable<impl Iterator<Item = (usize, char)>>,
+    end: usize,
+) -> Result<usize, ParseNetworkError> {
+    let mut offset = None;
+    let mut digits = String::new();
+    while let Some(&(pos, c)) = tokens.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        offset.get_or_insert(pos);
+        digits.push(c);
+        tokens.next();
+    }
+    let offset = offset.ok_or_else(|| ParseNetworkError::InvalidSyntax {
+        offset: tokens.peek().map_or(end, |&(pos, _)| pos),
+    })?;
+    digits
+        .parse::<usize>()
+        .ok()
+        .filter(|&channel| channel < u32::MAX_CHANNELS)
+        .ok_or(ParseNetworkError::InvalidChannel { offset })
+}
+
+impl Network {
+    pub fn new(channels: usize, comparators: Vec<(usize, usize)>) -> Self {
+        assert!(comparators
+            .iter()
+            .all(|&(a, b)| a != b && a < channels && b < channels));
+        Self {
+            channels,
+            comparators,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn comparators(&self) -> &[(usize, usize)] {
+        &self.comparators
+    }
+
+    pub fn push(&mut self, a: usize, b: usize) {
+        assert_ne!(a, b);
+        assert!(a < self.channels && b < self.channels);
+        self.comparators.push((a, b));
+    }
+
+    pub fn is_standard(&self) -> bool {
+        self.comparators.iter().all(|&(a, b)| a < b)
+    }
+
+    /// Rewrites a generalized network into a standard network of the same size.
+    ///
+    /// Each comparator `(a, b)` with `a > b` is replaced by `(b, a)` and channels `a` and `b` are
+    /// exchanged in all following comparators. The output set of the result is the output set of
+    /// this network with permuted channels, so a generalized sorting network becomes a standard
+    /// sorting network.
+    pub fn untangle(&self) -> Network {
+        let mut relabel = (0..self.channels).collect::<Vec<_>>();
+        let mut comparators = Vec::with_capacity(self.size());
+
+        for &(a, b) in self.comparators.iter() {
+            let (a, b) = (relabel[a], relabel[b]);
+            if a < b {
+                comparators.push((a, b));
+            } else {
+                comparators.push((b, a));
+                for channel in relabel.iter_mut() {
+                    if *channel == a {
+                        *channel = b;
+                    } else if *channel == b {
+                        *channel = a;
+                    }
+                }
+            }
+        }
+
+        Network::new(self.channels, comparators)
+    }
+
+    /// Number of comparators.
+    pub fn size(&self) -> usize {
+        self.comparators.len()
+    }
+
+    /// Number of parallel layers when each comparator is placed as early as possible.
+    pub fn depth(&self) -> usize {
+        let mut channel_depth = vec![0; self.channels];
+        for &(a, b) in self.comparators.iter() {
+            let depth = channel_depth[a].max(channel_depth[b]) + 1;
+            channel_depth[a] = depth;
+            channel_depth[b] = depth;
+        }
+        channel_depth.into_iter().max().unwrap_or(0)
+    }
+
+    /// Groups the comparators into parallel layers, placing each comparator as early as possible.
+    ///
+    /// Concatenating the layers gives a network that is equivalent to this one.
+    pub fn layers(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut channel_depth = vec![0; self.channels];
+        let mut layers: Vec<Vec<(usize, usize)>> = vec![];
+        for &(a, b) in self.comparators.iter() {
+            let depth = channel_depth[a].max(channel_depth[b]);
+            channel_depth[a] = depth + 1;
+            channel_depth[b] = depth + 1;
+            if depth == layers.len() {
+                layers.push(vec![]);
+            }
+            layers[depth].push((a, b));
+        }
+        layers
+    }
+
+    /// Returns the index and kind of every comparator that is redundant at its position.
+    ///
+    /// This is the same condition the search uses to skip comparators, i.e. one channel implies
+    /// the other for all outputs of the preceding comparators.
+    pub fn redundant_comparators(&self) -> Vec<(usize, Redundancy)> {
+        let mut swaps = vec![false; self.size()];
+        let mut keeps = vec![false; self.size()];
+
+        for batch in 0..BitSlice::batches(self.channels) {
+            let mut slice = BitSlice::all_values_batch(self.channels, batch);
+            for (index, &(a, b)) in self.comparators.iter().enumerate() {
+                let (word_a, word_b) = (slice.word(a), slice.word(b));
+                swaps[index] |= word_b & !word_a & slice.lanes() != 0;
+                keeps[index] |= word_a & !word_b & slice.lanes() != 0;
+                slice.apply_comparator(a, b);
+            }
+        }
+
+        swaps
+            .into_iter()
+            .zip(keeps)
+            .enumerate()
+            .filter_map(|(index, (swaps, keeps))| match (swaps, keeps) {
+                (false, _) => Some((index, Redundancy::NeverSwaps)),
+                (true, false) => Some((index, Redundancy::AlwaysSwaps)),
+                (true, true) => None,
+            })
+            .collect()
+    }
+
+    /// Returns an equivalent network without the comparators that never swap.
+    ///
+    /// Comparators that always swap are kept, as removing them would permute the outputs.
+    pub fn without_redundant_comparators(&self) -> Network {
+        let mut keep = vec![true; self.size()];
+        for (index, redundancy) in self.redundant_comparators() {
+            if redundancy == Redundancy::NeverSwaps {
+                keep[index] = false;
+            }
+        }
+        Network::new(
+            self.channels,
+            self.comparators
+                .iter()
+                .zip(keep)
+                .filter(|&(_, keep)| keep)
+                .map(|(&comparator, _)| comparator)
+                .collect(),
+        )
+    }
+
+    pub fn apply<V: Value>(&self, output_set: &OutputSet<V>) -> OutputSet<V> {
+        assert_eq!(output_set.channels(), self.channels);
+        let mut output_set = output_set.clone();
+        for &(a, b) in self.comparators.iter() {
+            output_set = output_set.apply_comparator(a, b);
+        }
+        output_set
+    }
+
+    /// Output set of the network for all `2^channels` inputs.
+    pub fn output_set<V: Value>(&self) -> OutputSet<V> {
+        bit_sliced::output_set(self)
+    }
+
+    /// Checks whether this network sorts all inputs, using the 0-1 principle.
+    pub fn is_sorting_network(&self) -> bool {
+        bit_sliced::is_sorting_network(self)
+    }
+}
+
+/// Returns every maximal set of pairwise disjoint comparators out of the given comparators.
+///
+/// A set is maximal when none of the given comparators can be added without sharing a channel.
+pub fn maximal_layers(channels: usize, comparators: &[(usize, usize)]) -> Vec<Vec<(usize, usize)>> {
+    fn search(
+        channels: usize,
+        comparators: &[(usize, usize)],
+        channel: usize,
+        used: &mut [bool],
+        unmatched: &mut [bool],
+        layer: &mut Vec<(usize, usize)>,
+        layers: &mut Vec<Vec<(usize, usize)>>,
+    ) {
+        if channel == channels {
+            if comparators
+                .iter()
+                .all(|&(a, b)| !(unmatched[a] && unmatched[b]))
+            {
+                layers.push(layer.clone());
+            }
+            return;
+        }
+
+        if used[channel] {
+            search(
+                channels,
+                comparators,
+                channel + 1,
+                used,
+                unmatched,
+                layer,
+                layers,
+            );
+            return;
+        }
+
+        for &(a, b) in comparators.iter() {
+            let other = if a == channel {
+                b
+            } else if b == channel {
+                a
+            } else {
+                continue;
+            };
+            if other < channel || used[other] {
+                continue;
+            }
+            used[channel] = true;
+            used[other] = true;
+            layer.push((a, b));
+            search(
+                channels,
+                comparators,
+                channel + 1,
+                used,
+                unmatched,
+                layer,
+                layers,
+            );
+            layer.pop();
+            used[channel] = false;
+            used[other] = false;
+        }
+
+        unmatched[channel] = true;
+        search(
+            channels,
+            comparators,
+            channel + 1,
+            used,
+            unmatched,
+            layer,
+            layers,
+        );
+        unmatched[channel] = false;
+    }
+
+    assert!(comparators
+        .iter()
+        .all(|&(a, b)| a != b && a < channels && b < channels));
+
+    let mut layers = vec![];
+    search(
+        channels,
+        comparators,
+        0,
+        &mut vec![false; channels],
+        &mut vec![false; channels],
+        &mut vec![],
+        &mut layers,
+    );
+    layers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::target::{Target, TargetSpec};
+
+    static SORT_11: &str = "[(0,9),(1,6),(2,4),(3,7),(5,8),
+        (0,1),(3,5),(4,10),(6,9),(7,8),
+        (1,3),(2,5),(4,7),(8,10),
+        (0,4),(1,2),(3,7),(5,9),(6,8),
+        (0,1),(2,6),(4,5),(7,8),(9,10),
+        (2,4),(3,6),(5,7),(8,9),
+        (1,2),(3,4),(5,6),(7,8),
+        (2,3),(4,5),(6,7)]";
+
+    #[test]
+    fn sort_11_network() {
+        let network = SORT_11.parse::<Network>().unwrap();
+
+        assert_eq!(network.channels(), 11);
+        assert_eq!(network.size(), 35);
+        assert_eq!(network.depth(), 8);
+        assert!(network.is_sorting_network());
+
+        let layers = network.layers();
+        assert_eq!(layers.len(), 8);
+        assert_eq!(layers.iter().map(|layer| layer.len()).sum::<usize>(), 35);
+        for layer in layers.iter() {
+            let mut used = [false; 11];
+            for &(a, b) in layer.iter() {
+                assert!(!used[a] && !used[b]);
+                used[a] = true;
+                used[b] = true;
+            }
+        }
+
+        let relayered = Network::new(11, layers.concat());
+        assert_eq!(relayered.output_set::<u16>(), network.output_set::<u16>());
+
+        assert_eq!(network.to_string().parse::<Network>(), Ok(network.clone()));
+
+        let mut truncated = Network::new(11, network.comparators()[..34].to_vec());
+        assert!(!truncated.is_sorting_network());
+        truncated.push(6, 7);
+        assert!(truncated.is_sorting_network());
+    }
+
+    #[test]
+    fn untangle_networks() {
+        let network = SORT_11.parse::<Network>().unwrap();
+        assert!(network.is_standard());
+        assert_eq!(network.untangle(), network);
+
+        let mut tangled = network.clone();
+        for &pos in [30, 21, 17, 9, 8, 2].iter() {
+            let (a, b) = tangled.comparators[pos];
+            tangled.comparators[pos] = (b, a);
+            for comparator in tangled.comparators[pos + 1..].iter_mut() {
+                for channel in [&mut comparator.0, &mut comparator.1].iter_mut() {
+                    if **channel == a {
+                        **channel = b;
+                    } else if **channel == b {
+                        **channel = a;
+                    }
+                }
+            }
+        }
+
+        assert!(!tangled.is_standard());
+        assert!(!tangled.is_sorting_network());
+        assert!(TargetSpec::Sorting.is_satisfied(&tangled.output_set::<u16>()));
+
+        let untangled = tangled.untangle();
+        assert!(untangled.is_standard());
+        assert!(untangled.is_sorting_network());
+        assert_eq!(untangled, network);
+
+        let generalized = Network::new(
+            7,
+            vec![
+                (3, 1),
+                (0, 6),
+                (5, 2),
+                (2, 0),
+                (4, 3),
+                (6, 1),
+                (1, 5),
+                (3, 0),
+            ],
+        );
+        let untangled = generalized.untangle();
+        assert!(untangled.is_standard());
+        assert_eq!(untangled.size(), generalized.size());
+        assert_eq!(
+            untangled.output_set::<u16>().canonical_form().0,
+            generalized.output_set::<u16>().canonical_form().0
+        );
+    }
+
+    #[test]
+    fn redundant_comparators() {
+        let network = SORT_11.parse::<Network>().unwrap();
+
+        assert_eq!(network.redundant_comparators(), vec![]);
+
+        let mut padded = Network::new(11, vec![(3, 4)]);
+        for &(a, b) in network.comparators() {
+            padded.push(a, b);
+            if (a, b) == (0, 9) {
+                padded.push(0, 9);
+                padded.push(9, 0);
+            }
+        }
+        padded.push(0, 10);
+
+        assert_eq!(
+            padded.redundant_comparators(),
+            vec![
+                (2, Redundancy::NeverSwaps),
+                (3, Redundancy::AlwaysSwaps),
+                (38, Redundancy::NeverSwaps),
+            ]
+        );
+
+        let mut expected = Network::new(11, vec![(3, 4), (0, 9), (9, 0)]);
+        for &(a, b) in network.comparators()[1..].iter() {
+            expected.push(a, b);
+        }
+        let reduced = padded.without_redundant_comparators();
+        assert_eq!(reduced, expected);
+        assert_eq!(reduced.output_set::<u16>(), padded.output_set::<u16>());
+        assert_eq!(
+            reduced.redundant_comparators(),
+            vec![(2, Redundancy::AlwaysSwaps)]
+        );
+    }
+
+    #[test]
+    fn maximal_layer_counts() {
+        let all_pairs = |channels: usize| {
+            (0..channels)
+                .flat_map(|j| (0..j).map(move |i| (i, j)))
+                .collect::<Vec<_>>()
+        };
+
+        let counts = (1..=7)
+            .map(|channels| maximal_layers(channels, &all_pairs(channels)).len())
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![1, 1, 3, 3, 15, 15, 105]);
+
+        let path = [(0, 1), (1, 2), (2, 3)];
+        assert_eq!(
+            maximal_layers(4, &path),
+            vec![vec![(0, 1), (2, 3)], vec![(1, 2)]]
+        );
+
+        assert_eq!(maximal_layers(3, &[]), vec![vec![]]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        use ParseNetworkError::*;
+
+        let parse = |text: &str| text.parse::<Network>().map(|n| n.comparators().to_vec());
+
+        assert_eq!(parse("[]"), Ok(vec![]));
+        assert_eq!(parse(" [ ( 0 , 1 ) , (2,3), ] "), Ok(vec![(0, 1), (2, 3)]));
+        assert_eq!(
+            parse("[[(0,1),(2,3)],[(1,2)]]"),
+            Ok(vec![(0, 1), (2, 3), (1, 2)])
+        );
+        assert_eq!(parse("[(0,1"), Err(InvalidSyntax { offset: 5 }));
+        assert_eq!(parse("[(0;1)]"), Err(InvalidSyntax { offset: 3 }));
+        assert_eq!(parse("[(0,)]"), Err(InvalidSyntax { offset: 4 }));
+        assert_eq!(parse("[0,1]"), Err(InvalidSyntax { offset: 1 }));
+        assert_eq!(parse("[(1,1)]"), Err(SameChannel { offset: 1 }));
+        assert_eq!(
+            parse("[(0,99999999999999999999999)]"),
+            Err(InvalidChannel { offset: 4 })
+        );
+    }
+}
@@ -1,36 +1,34 @@
 use std::{
-    cmp::{max, min},
+    cmp::{max, min, Ordering},
     fmt,
+    iter::once,
 };
 
-pub const MAX_CHANNELS: usize = 15;
+use crate::value::Value;
 
-pub type CVec<T> = arrayvec::ArrayVec<[T; MAX_CHANNELS]>;
-
-const MAX_VALUES: usize = 1 << MAX_CHANNELS;
-
-type VVec<T> = arrayvec::ArrayVec<[T; MAX_VALUES]>;
+/// Largest number of channels supported by any value type.
+pub const MAX_CHANNELS: usize = 24;
 
-type AVec<T> = arrayvec::ArrayVec<[T; 512]>;
+pub type CVec<T> = arrayvec::ArrayVec<[T; MAX_CHANNELS]>;
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
-pub struct OutputSet {
+pub struct OutputSet<V: Value = u16> {
     channels: usize,
-    values: Vec<u16>,
+    values: Vec<V>,
 }
 
-impl fmt::Debug for OutputSet {
+impl<V: Value> fmt::Debug for OutputSet<V> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        struct WrapValue(usize, u16);
-        impl fmt::Debug for WrapValue {
+        struct WrapValue<V>(usize, V);
+        impl<V: Value> fmt::Debug for WrapValue<V> {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
                 write!(f, "0b{0:01$b}", self.1, self.0)
             }
         }
 
-        struct WrapValues<'a>(usize, &'a Vec<u16>);
+        struct WrapValues<'a, V>(usize, &'a Vec<V>);
 
-        impl<'a> fmt::Debug for WrapValues<'a> {
+        impl<'a, V: Value> fmt::Debug for WrapValues<'a, V> {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
                 f.debug_list()
                     .entries(self.1.iter().map(|&v| WrapValue(self.0, v)))
@@ -45,37 +43,211 @@ impl fmt::Debug for OutputSet {
     }
 }
 
-impl OutputSet {
+/// Text format of an output set.
+///
+/// The first line is `channels <N>`, followed by one line per value in ascending order. Each value
+/// is written as `N` binary digits, with the highest channel first, as in the `Debug` output. With
+/// zero channels the only possible value is written as a single `0`. When parsing, values may
+/// appear in any order and empty lines or lines starting with `#` are ignored.
+impl<V: Value> fmt::Display for OutputSet<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "channels {}", self.channels)?;
+        for &value in self.values.iter() {
+            writeln!(f, "{0:01$b}", value, self.channels)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when parsing the text format of an output set fails.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ParseOutputSetError {
+    MissingHeader,
+    InvalidHeader { line: usize },
+    TooManyChannels { line: usize, channels: usize },
+    WrongWidth { line: usize, channels: usize },
+    InvalidDigit { line: usize },
+    Duplicate { line: usize },
+}
+
+impl fmt::Display for ParseOutputSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseOutputSetError::MissingHeader => write!(f, "missing `channels` header"),
+            ParseOutputSetError::InvalidHeader { line } => {
+                write!(f, "line {}: expected `channels <N>`", line)
+            }
+            ParseOutputSetError::TooManyChannels { line, channels } => write!(
+                f,
+                "line {}: {} channels are not supported by this value type",
+                line, channels
+            ),
+            ParseOutputSetError::WrongWidth { line, channels } => {
+                write!(
+                    f,
+                    "line {}: expected a value with {} digits",
+                    line, channels
+                )
+            }
+            ParseOutputSetError::InvalidDigit { line } => {
+                write!(f, "line {}: values may only contain `0` and `1`", line)
+            }
+            ParseOutputSetError::Duplicate { line } => write!(f, "line {}: duplicate value", line),
+        }
+    }
+}
+
+impl std::error::Error for ParseOutputSetError {}
+
+impl<V: Value> std::str::FromStr for OutputSet<V> {
+    type Err = ParseOutputSetError;
+
+    fn from_str(s: &str) -> Result<Self, ParseOutputSetError> {
+        let mut lines = s
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        let (header_line, header) = lines.next().ok_or(ParseOutputSetError::MissingHeader)?;
+
+        let invalid_header = ParseOutputSetError::InvalidHeader { line: header_line };
+
+        let mut header_words = header.split_whitespace();
+        if header_words.next() != Some("channels") {
+            return Err(invalid_header);
+        }
+        let channels = header_words
+            .next()
+            .and_then(|word| word.parse::<usize>().ok())
+            .ok_or_else(|| invalid_header.clone())?;
+        if header_words.next().is_some() {
+            return Err(invalid_header);
+        }
+
+        if channels > V::MAX_CHANNELS {
+            return Err(ParseOutputSetError::TooManyChannels {
+                line: header_line,
+                channels,
+            });
+        }
+
+        let mut values = vec![];
+        let mut lines_of_values = vec![];
+
+        for (line, word) in lines {
+            if word.len() != channels.max(1) {
+                return Err(ParseOutputSetError::WrongWidth { line, channels });
+            }
+            if !word.bytes().all(|digit| digit == b'0' || digit == b'1') {
+                return Err(ParseOutputSetError::InvalidDigit { line });
+            }
+            let value = usize::from_str_radix(word, 2).unwrap_or(0);
+            if value >> channels != 0 {
+                return Err(ParseOutputSetError::WrongWidth { line, channels });
+            }
+            values.push(V::from_usize(value));
+            lines_of_values.push((value, line));
+        }
+
+        lines_of_values.sort_unstable();
+        for (a, b) in lines_of_values.iter().zip(lines_of_values.iter().skip(1)) {
+            if a.0 == b.0 {
+                return Err(ParseOutputSetError::Duplicate { line: b.1 });
+            }
+        }
+
+        Ok(Self::from_values(channels, values))
+    }
+}
+
+impl<V: Value> OutputSet<V> {
     pub fn all_values(channels: usize) -> Self {
-        assert!(channels <= MAX_CHANNELS);
+        assert!(channels <= V::MAX_CHANNELS);
         Self {
             channels,
-            values: (0..1 << channels).collect(),
+            values: (0..1 << channels).map(V::from_usize).collect(),
+        }
+    }
+
+    /// All values that are sorted within each block of consecutive channels.
+    ///
+    /// The blocks are given by their sizes, starting at channel 0, and have to cover all channels.
+    pub fn presorted_blocks(block_sizes: &[usize]) -> Self {
+        let channels = block_sizes.iter().sum();
+        assert!(channels <= V::MAX_CHANNELS);
+
+        let mut values = vec![V::ZERO];
+        let mut offset = 0;
+
+        for &size in block_sizes {
+            values = values
+                .into_iter()
+                .flat_map(|value| {
+                    (0..=size).map(move |ones| value | (((V::ONE << ones) - V::ONE) << offset))
+                })
+                .collect();
+            offset += size;
         }
+
+        Self::from_values(channels, values)
     }
 
+    /// All values with at most `max_inversions` pairs of channels that are out of order.
+    pub fn bounded_inversions(channels: usize, max_inversions: usize) -> Self {
+        assert!(channels <= V::MAX_CHANNELS);
+        Self {
+            channels,
+            values: (0..1usize << channels)
+                .filter(|&value| {
+                    let inversions: usize = (0..channels)
+                        .filter(|&channel| value & (1 << channel) != 0)
+                        .map(|channel| {
+                            channel - (value & ((1 << channel) - 1)).count_ones() as usize
+                        })
+                        .sum();
+                    inversions <= max_inversions
+                })
+                .map(V::from_usize)
+                .collect(),
+        }
+    }
+
+    pub fn from_values(channels: usize, mut values: Vec<V>) -> Self {
+        assert!(channels <= V::MAX_CHANNELS);
+        assert!(values.iter().all(|&value| value.to_usize() < 1 << channels));
+        values.sort_unstable();
+        values.dedup();
+        Self { channels, values }
+    }
+
+    /// Applies a comparator that puts the larger value on channel `a` and the smaller value on
+    /// channel `b`.
+    ///
+    /// A standard comparator has `a < b`, so that sorted values have their ones in the low
+    /// channels. Generalized comparators with `a > b` sort in the other direction.
     pub fn apply_comparator(&self, a: usize, b: usize) -> Self {
         assert_ne!(a, b);
         assert!(a < self.channels && b < self.channels);
 
-        let mask_a = 1 << a;
-        let mask_b = 1 << b;
+        let mask_a = V::ONE << a;
+        let mask_b = V::ONE << b;
 
         let mask = mask_a | mask_b;
 
-        let mut keep = VVec::<u16>::new();
-        let mut swap = VVec::<u16>::new();
+        let mut keep = V::Buffer::default();
+        let mut swap = V::Buffer::default();
 
         for &value in self.values.iter() {
             if value & mask == mask_b {
-                swap.push(value ^ mask);
+                swap.extend(once(value ^ mask));
             } else {
-                keep.push(value);
+                keep.extend(once(value));
             }
         }
 
-        swap.push(!0);
-        keep.push(!0);
+        swap.extend(once(!V::ZERO));
+        keep.extend(once(!V::ZERO));
 
         let mut values = Vec::with_capacity(self.values.len());
 
@@ -86,7 +258,7 @@ impl OutputSet {
             let swap_value = swap[swap_pos];
             let keep_value = keep[keep_pos];
             let value = min(swap_value, keep_value);
-            if value == !0 {
+            if value == !V::ZERO {
                 break;
             }
             values.push(value);
@@ -105,32 +277,34 @@ impl OutputSet {
         self.channels
     }
 
-    pub fn values(&self) -> &[u16] {
+    pub fn values(&self) -> &[V] {
         &self.values
     }
 
     pub fn is_sorted(&self) -> bool {
-        self.values.iter().all(|&value| value & (value + 1) == 0)
+        self.values
+            .iter()
+            .all(|&value| value & (value + V::ONE) == V::ZERO)
     }
 
-    pub fn abstraction(&self) -> Abstraction {
-        let channel_values_len = Abstraction::channel_values_len(self.channels);
+    pub fn abstraction(&self) -> Abstraction<V> {
+        let channel_values_len = Abstraction::<V>::channel_values_len(self.channels);
 
         let mut values = (0..self.channels * channel_values_len)
-            .map(|_| 0)
-            .collect::<AVec<u16>>();
+            .map(|_| V::ZERO)
+            .collect::<V::AbstractionValues>();
 
         for &value in self.values.iter() {
             let pop_count = value.count_ones() as usize;
             for channel in 0..self.channels {
-                let mask = 1 << channel;
-                let channel_value = (value & mask != 0) as usize;
+                let mask = V::ONE << channel;
+                let channel_value = (value & mask != V::ZERO) as usize;
 
                 let channel_pop_count = pop_count - channel_value;
 
                 values[channel_values_len * channel + 2 * channel_pop_count + 2 + channel_value] +=
-                    1;
-                values[channel_values_len * channel + channel_value] += 1;
+                    V::ONE;
+                values[channel_values_len * channel + channel_value] += V::ONE;
             }
         }
 
@@ -140,14 +314,14 @@ impl OutputSet {
         }
     }
 
-    pub fn channel_weights(&self) -> CVec<u16> {
-        let mut weights = (0..self.channels).map(|_| 0).collect::<CVec<u16>>();
+    pub fn channel_weights(&self) -> CVec<V> {
+        let mut weights = (0..self.channels).map(|_| V::ZERO).collect::<CVec<V>>();
 
         for &value in self.values.iter() {
             for channel in 0..self.channels {
-                let mask = 1 << channel;
-                let channel_value = value & mask != 0;
-                weights[channel] += channel_value as u16;
+                let mask = V::ONE << channel;
+                let channel_value = value & mask != V::ZERO;
+                weights[channel] += V::from_usize(channel_value as usize);
             }
         }
 
@@ -160,22 +334,22 @@ impl OutputSet {
         let perm_masks = perm
             .into_iter()
             .rev()
-            .map(|j| 1u16 << j)
+            .map(|j| V::ONE << j)
             .collect::<CVec<_>>();
 
-        let mut mask_combined = 0;
+        let mut mask_combined = V::ZERO;
 
         for &mask_from in perm_masks.iter() {
             mask_combined |= mask_from;
         }
 
-        assert_eq!(mask_combined + 1, 1 << self.channels);
+        assert_eq!(mask_combined + V::ONE, V::ONE << self.channels);
 
         for value in self.values.iter_mut() {
-            let mut value_out = 0;
-            for mask_from in perm_masks.iter() {
-                value_out <<= 1;
-                value_out |= (*value & mask_from != 0) as u16;
+            let mut value_out = V::ZERO;
+            for &mask_from in perm_masks.iter() {
+                value_out = value_out << 1;
+                value_out |= V::from_usize((*value & mask_from != V::ZERO) as usize);
             }
             *value = value_out;
         }
@@ -200,34 +374,102 @@ impl OutputSet {
         perm
     }
 
+    /// Returns the representative of all output sets that are equal to this output set up to a
+    /// permutation of channels, together with the permutation that turns this output set into the
+    /// representative.
+    ///
+    /// Channels are first grouped by their part of the abstraction, which does not depend on the
+    /// channel order. Starting with the most significant channel, each position is then filled
+    /// with a channel of the corresponding group, branching over all choices and keeping only
+    /// those that minimize the values projected onto the already filled positions.
+    pub fn canonical_form(&self) -> (Self, CVec<usize>) {
+        let abstraction = self.abstraction();
+
+        let mut order = (0..self.channels).collect::<CVec<_>>();
+        order.sort_by(|&a, &b| {
+            abstraction
+                .channel_values(a)
+                .cmp(abstraction.channel_values(b))
+        });
+
+        let mut cells = (0..self.channels).map(|_| 0).collect::<CVec<_>>();
+        for (prev, &channel) in order.iter().zip(order.iter().skip(1)) {
+            cells[channel] = cells[*prev]
+                + (abstraction.channel_values(*prev) != abstraction.channel_values(channel))
+                    as usize;
+        }
+
+        let mut states = vec![(self.clone(), (0..self.channels).collect::<CVec<_>>())];
+
+        for (depth, &target) in order.iter().enumerate() {
+            let position = self.channels - 1 - depth;
+            let target_cell = cells[target];
+
+            let mut next_states: Vec<(Self, CVec<usize>)> = vec![];
+
+            for (output_set, perm) in states.iter() {
+                for candidate in 0..=position {
+                    if cells[perm[candidate]] != target_cell {
+                        continue;
+                    }
+
+                    let mut next_output_set = output_set.clone();
+                    next_output_set.swap_channels(candidate, position);
+
+                    if let Some((best_output_set, _)) = next_states.first() {
+                        let projected = next_output_set.values.iter().map(|&v| v >> position);
+                        let best_projected = best_output_set.values.iter().map(|&v| v >> position);
+                        match projected.cmp(best_projected) {
+                            Ordering::Less => next_states.clear(),
+                            Ordering::Equal => (),
+                            Ordering::Greater => continue,
+                        }
+                    }
+
+                    let mut next_perm = perm.clone();
+                    next_perm.swap(candidate, position);
+
+                    next_states.push((next_output_set, next_perm));
+                }
+            }
+
+            next_states.sort_unstable_by(|(a, _), (b, _)| a.values.cmp(&b.values));
+            next_states.dedup_by(|(a, _), (b, _)| a.values == b.values);
+
+            states = next_states;
+        }
+
+        states.pop().unwrap()
+    }
+
     pub fn swap_channels(&mut self, a: usize, b: usize) {
         assert!(a < self.channels && b < self.channels);
         if a == b {
             return;
         }
 
-        let mask_a = 1 << a;
-        let mask_b = 1 << b;
+        let mask_a = V::ONE << a;
+        let mask_b = V::ONE << b;
 
         let mask = mask_a | mask_b;
 
-        let mut keep = VVec::<u16>::new();
-        let mut swap_a = VVec::<u16>::new();
-        let mut swap_b = VVec::<u16>::new();
+        let mut keep = V::Buffer::default();
+        let mut swap_a = V::Buffer::default();
+        let mut swap_b = V::Buffer::default();
 
         for &value in self.values.iter() {
             if value & mask == mask_a {
-                swap_a.push(value ^ mask);
+                swap_a.extend(once(value ^ mask));
             } else if value & mask == mask_b {
-                swap_b.push(value ^ mask);
+                swap_b.extend(once(value ^ mask));
             } else {
-                keep.push(value);
+                keep.extend(once(value));
             }
         }
 
-        swap_a.push(!0);
-        swap_b.push(!0);
-        keep.push(!0);
+        swap_a.extend(once(!V::ZERO));
+        swap_b.extend(once(!V::ZERO));
+        keep.extend(once(!V::ZERO));
 
         self.values.clear();
 
@@ -240,7 +482,7 @@ impl OutputSet {
             let swap_b_value = swap_b[swap_b_pos];
             let keep_value = keep[keep_pos];
             let value = min(min(swap_a_value, swap_b_value), keep_value);
-            if value == !0 {
+            if value == !V::ZERO {
                 break;
             }
             self.values.push(value);
@@ -251,7 +493,37 @@ impl OutputSet {
         }
     }
 
-    pub fn subsumes(&self, other: &OutputSet) -> bool {
+    /// Reverses the channel order and complements every value.
+    ///
+    /// Sorting networks are closed under this reflection, so a set subsumes another set if it
+    /// subsumes the reflection of it.
+    pub fn reflect(&self) -> Self {
+        let shift = V::BITS - self.channels;
+        let mask = V::from_usize((1 << self.channels) - 1);
+
+        let mut values = self
+            .values
+            .iter()
+            .map(|&value| {
+                let reversed = value.reverse_bits();
+                let reversed = if shift == V::BITS {
+                    V::ZERO
+                } else {
+                    reversed >> shift
+                };
+                !reversed & mask
+            })
+            .collect::<Vec<_>>();
+
+        values.sort_unstable();
+
+        Self {
+            channels: self.channels,
+            values,
+        }
+    }
+
+    pub fn subsumes(&self, other: &OutputSet<V>) -> bool {
         if other.values.len() < self.values.len() {
             return false;
         }
@@ -291,6 +563,7 @@ impl OutputSet {
             .collect::<CVec<_>>();
 
         for &value in self.values.iter() {
+            let value = value.to_usize() as u32;
             for channel in 0..self.channels {
                 let channel_mask = 1 << channel;
                 if value & channel_mask != 0 {
@@ -304,13 +577,13 @@ impl OutputSet {
 }
 
 #[derive(Clone, Debug)]
-pub struct Abstraction {
+pub struct Abstraction<V: Value = u16> {
     channels: usize,
-    values: AVec<u16>,
+    values: V::AbstractionValues,
 }
 
-impl Abstraction {
-    pub fn update_min(&mut self, other: &Abstraction) {
+impl<V: Value> Abstraction<V> {
+    pub fn update_min(&mut self, other: &Abstraction<V>) {
         assert_eq!(self.channels, other.channels);
 
         for (my, other) in self.values.iter_mut().zip(other.values.iter()) {
@@ -318,7 +591,7 @@ impl Abstraction {
         }
     }
 
-    pub fn update_max(&mut self, other: &Abstraction) {
+    pub fn update_max(&mut self, other: &Abstraction<V>) {
         assert_eq!(self.channels, other.channels);
 
         for (my, other) in self.values.iter_mut().zip(other.values.iter()) {
@@ -326,22 +599,34 @@ impl Abstraction {
         }
     }
 
-    pub fn values(&self) -> &[u16] {
+    pub fn values(&self) -> &[V] {
         &self.values
     }
 
-    pub fn largest_range(&self, other: &Abstraction) -> Option<usize> {
+    /// Returns the part of the abstraction that belongs to a single channel.
+    pub fn channel_values(&self, channel: usize) -> &[V] {
+        let channel_values_len = Self::channel_values_len(self.channels);
+        let offset = channel_values_len * channel;
+        &self.values[offset..offset + channel_values_len]
+    }
+
+    pub fn largest_range(&self, other: &Abstraction<V>) -> Option<usize> {
         self.values
             .iter()
             .zip(other.values.iter())
             .map(|(&a, &b)| max(a, b) - min(a, b))
             .enumerate()
             .max_by_key(|&(_, range)| range)
-            .filter(|&(_, range)| range > 0)
+            .filter(|&(_, range)| range > V::ZERO)
             .map(|(index, _)| index)
     }
 
-    pub fn channel_le(&self, my_channel: usize, other: &Abstraction, other_channel: usize) -> bool {
+    pub fn channel_le(
+        &self,
+        my_channel: usize,
+        other: &Abstraction<V>,
+        other_channel: usize,
+    ) -> bool {
         assert_eq!(self.channels, other.channels);
 
         let channel_values_len = Self::channel_values_len(self.channels);
@@ -373,12 +658,40 @@ impl Abstraction {
         }
     }
 
+    /// Returns the abstraction of the reflected output set, see [`OutputSet::reflect`].
+    pub fn reflect(&self) -> Abstraction<V> {
+        let channel_values_len = Self::channel_values_len(self.channels);
+
+        let mut values = self.values.clone();
+
+        for channel in 0..self.channels {
+            let offset = channel_values_len * channel;
+            let reflected_offset = channel_values_len * (self.channels - 1 - channel);
+
+            for channel_value in 0..2 {
+                let reflected_value = 1 - channel_value;
+                values[reflected_offset + reflected_value] = self.values[offset + channel_value];
+
+                for channel_pop_count in 0..self.channels {
+                    let reflected_pop_count = self.channels - 1 - channel_pop_count;
+                    values[reflected_offset + 2 * reflected_pop_count + 2 + reflected_value] =
+                        self.values[offset + 2 * channel_pop_count + 2 + channel_value];
+                }
+            }
+        }
+
+        Abstraction {
+            channels: self.channels,
+            values,
+        }
+    }
+
     fn channel_values_len(channels: usize) -> usize {
         channels * 2 + 2
     }
 }
 
-pub struct Implications(CVec<u16>);
+pub struct Implications(CVec<u32>);
 
 impl Implications {
     pub fn implies(&self, a: usize, b: usize) -> bool {
@@ -397,8 +710,8 @@ mod test {
     #[test]
     fn avec_capacity() {
         assert!(
-            AVec::<usize>::new().capacity()
-                >= MAX_CHANNELS * Abstraction::channel_values_len(MAX_CHANNELS)
+            crate::value::AVec::<usize>::new().capacity()
+                >= u16::MAX_CHANNELS * Abstraction::<u16>::channel_values_len(u16::MAX_CHANNELS)
         );
     }
 
@@ -418,7 +731,7 @@ mod test {
     fn sort_11_sorts() {
         crate::logging::setup();
 
-        let mut output_set = OutputSet::all_values(11);
+        let mut output_set: OutputSet = OutputSet::all_values(11);
 
         assert_eq!(
             output_set.implications().0,
@@ -446,7 +759,7 @@ mod test {
     fn sort_11_order_channels() {
         crate::logging::setup();
 
-        let mut output_set = OutputSet::all_values(11);
+        let mut output_set: OutputSet = OutputSet::all_values(11);
 
         for (i, &(a, b)) in SORT_11.iter().enumerate() {
             assert!(!output_set.is_sorted());
@@ -490,4 +803,243 @@ mod test {
 
         log::info!("result: {:?}", output_set);
     }
+
+    #[test]
+    fn sort_11_reflect() {
+        let mut output_set: OutputSet = OutputSet::all_values(11);
+
+        for &(a, b) in SORT_11.iter() {
+            let reflected = output_set.reflect();
+
+            assert_eq!(reflected.values().len(), output_set.values().len());
+            assert_eq!(reflected.reflect(), output_set);
+            assert_eq!(
+                reflected.abstraction().values(),
+                output_set.abstraction().reflect().values()
+            );
+
+            assert_eq!(
+                reflected.apply_comparator(10 - b, 10 - a),
+                output_set.apply_comparator(a, b).reflect()
+            );
+
+            output_set = output_set.apply_comparator(a, b);
+        }
+
+        assert_eq!(output_set.reflect(), output_set);
+    }
+
+    #[test]
+    fn sort_11_wide() {
+        let mut output_set: OutputSet = OutputSet::all_values(11);
+        let mut wide_output_set = OutputSet::<u32>::all_values(11);
+
+        for &(a, b) in SORT_11.iter() {
+            output_set = output_set.apply_comparator(a, b);
+            wide_output_set = wide_output_set.apply_comparator(a, b);
+
+            assert!(output_set
+                .values()
+                .iter()
+                .map(|&value| value as u32)
+                .eq(wide_output_set.values().iter().cloned()));
+        }
+
+        assert!(wide_output_set.is_sorted());
+
+        let mut wide_output_set = OutputSet::<u32>::all_values(17);
+
+        for &(a, b) in SORT_11.iter() {
+            wide_output_set = wide_output_set.apply_comparator(a + 6, b + 6);
+        }
+
+        assert_eq!(wide_output_set.values().len(), 12 << 6);
+        assert_eq!(wide_output_set.reflect().reflect(), wide_output_set);
+        assert!(!wide_output_set.is_sorted());
+    }
+
+    #[test]
+    fn sort_11_canonical_form() {
+        let mut output_set: OutputSet = OutputSet::all_values(11);
+
+        for &(a, b) in SORT_11.iter() {
+            let (canonical, perm) = output_set.canonical_form();
+
+            let mut permuted = output_set.clone();
+            permuted.permute_channels(perm);
+            assert_eq!(permuted, canonical);
+
+            for offset in 1..11 {
+                let mut permuted = output_set.clone();
+                permuted.permute_channels((0..11).map(|i| (i * 3 + offset) % 11).collect());
+                assert_eq!(permuted.canonical_form().0, canonical);
+            }
+
+            output_set = output_set.apply_comparator(a, b);
+        }
+    }
+
+    #[test]
+    fn canonical_form_separates_orbits() {
+        let channels = 5;
+
+        let mut perms: Vec<CVec<usize>> = vec![(0..channels).collect()];
+        for i in 1..channels {
+            perms = perms
+                .into_iter()
+                .flat_map(|perm| {
+                    (0..=i).map(move |j| {
+                        let mut perm = perm.clone();
+                        perm.swap(i, j);
+                        perm
+                    })
+                })
+                .collect();
+        }
+
+        let mut output_sets = vec![];
+        let all_values: OutputSet = OutputSet::all_values(channels);
+        for j in 0..channels {
+            for i in 0..j {
+                let tmp = all_values.apply_comparator(i, j);
+                for j_2 in 0..channels {
+                    for i_2 in 0..j_2 {
+                        output_sets.push(tmp.apply_comparator(i_2, j_2));
+                    }
+                }
+            }
+        }
+
+        for a in output_sets.iter() {
+            let orbit = perms
+                .iter()
+                .map(|perm| {
+                    let mut permuted = a.clone();
+                    permuted.permute_channels(perm.clone());
+                    permuted
+                })
+                .collect::<Vec<_>>();
+
+            let canonical_a = a.canonical_form().0;
+
+            for b in output_sets.iter() {
+                assert_eq!(
+                    canonical_a == b.canonical_form().0,
+                    orbit.contains(b),
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn generalized_comparators() {
+        let mut output_set: OutputSet = OutputSet::all_values(6);
+        for &(a, b) in [(0, 3), (4, 1), (5, 2), (2, 3), (1, 0)].iter() {
+            let mut expected = output_set.apply_comparator(b, a);
+            expected.swap_channels(a, b);
+
+            output_set = output_set.apply_comparator(a, b);
+            assert_eq!(output_set, expected);
+            assert!(output_set
+                .values()
+                .iter()
+                .all(|&value| (value >> b) & 1 <= (value >> a) & 1));
+        }
+    }
+
+    #[test]
+    fn restricted_inputs() {
+        let merge: OutputSet = OutputSet::presorted_blocks(&[3, 5]);
+        assert_eq!(merge.channels(), 8);
+        assert_eq!(merge.values().len(), 4 * 6);
+        assert!(merge.values().iter().all(|&value| {
+            let (low, high) = (value & 0b111, value >> 3);
+            low & (low + 1) == 0 && high & (high + 1) == 0
+        }));
+
+        let unsorted: OutputSet = OutputSet::presorted_blocks(&[1; 6]);
+        assert_eq!(unsorted, OutputSet::all_values(6));
+
+        let sorted: OutputSet = OutputSet::bounded_inversions(7, 0);
+        assert!(sorted.is_sorted());
+        assert_eq!(sorted.values().len(), 8);
+
+        let one_inversion: OutputSet = OutputSet::bounded_inversions(7, 1);
+        assert_eq!(one_inversion.values().len(), 8 + 6);
+
+        assert_eq!(
+            OutputSet::<u16>::bounded_inversions(7, 12),
+            OutputSet::all_values(7)
+        );
+    }
+
+    #[test]
+    fn sort_11_text_format() {
+        let mut output_set: OutputSet = OutputSet::all_values(11);
+
+        for &(a, b) in SORT_11.iter() {
+            let text = output_set.to_string();
+            assert_eq!(text.parse::<OutputSet>(), Ok(output_set.clone()));
+            assert_eq!(
+                text.parse::<OutputSet<u32>>().map(|o| o.values().len()),
+                Ok(output_set.values().len())
+            );
+            output_set = output_set.apply_comparator(a, b);
+        }
+
+        assert_eq!(output_set.to_string().lines().nth(3), Some("00000000011"));
+
+        let empty_width: OutputSet = OutputSet::all_values(0);
+        assert_eq!(empty_width.to_string(), "channels 0\n0\n");
+        assert_eq!(empty_width.to_string().parse(), Ok(empty_width));
+    }
+
+    #[test]
+    fn text_format_errors() {
+        use ParseOutputSetError::*;
+
+        let parse = |text: &str| text.parse::<OutputSet>().map(|o| o.values().to_vec());
+
+        assert_eq!(
+            parse("# a comment\n\nchannels 3\n011\n 001 \n"),
+            Ok(vec![1, 3])
+        );
+        assert_eq!(parse("channels 0\n"), Ok(vec![]));
+        assert_eq!(parse("channels 0\n\n"), Ok(vec![]));
+        assert_eq!(parse("channels 0\n0\n"), Ok(vec![0]));
+        assert_eq!(
+            parse("channels 0\n1\n"),
+            Err(WrongWidth {
+                line: 2,
+                channels: 0
+            })
+        );
+        assert_eq!(parse(""), Err(MissingHeader));
+        assert_eq!(parse("channel 3\n"), Err(InvalidHeader { line: 1 }));
+        assert_eq!(parse("channels x\n"), Err(InvalidHeader { line: 1 }));
+        assert_eq!(parse("channels 3 4\n"), Err(InvalidHeader { line: 1 }));
+        assert_eq!(
+            parse("\nchannels 16\n"),
+            Err(TooManyChannels {
+                line: 2,
+                channels: 16
+            })
+        );
+        assert_eq!(
+            parse("channels 3\n001\n0001\n"),
+            Err(WrongWidth {
+                line: 3,
+                channels: 3
+            })
+        );
+        assert_eq!(parse("channels 3\n0a1\n"), Err(InvalidDigit { line: 2 }));
+        assert_eq!(parse("channels 3\n+01\n"), Err(InvalidDigit { line: 2 }));
+        assert_eq!(
+            parse("channels 3\n011\n001\n011\n"),
+            Err(Duplicate { line: 4 })
+        );
+    }
 }
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+
+use crate::{output_set::OutputSet, target::Target, value::Value};
+
+/// Representative of the output sets equivalent to `output_set`, i.e. those that subsume it and
+/// are subsumed by it.
+///
+/// These are the channel permutations of `output_set` and, if `reflection` is set, of its
+/// reflection.
+pub fn equivalence_class<V: Value>(output_set: &OutputSet<V>, reflection: bool) -> OutputSet<V> {
+    let canonical = output_set.canonical_form().0;
+    if reflection {
+        canonical.min(output_set.reflect().canonical_form().0)
+    } else {
+        canonical
+    }
+}
+
+/// Applies every comparator to every output set of a layer, merging the path counts of
+/// equivalent results.
+///
+/// Unlike [`incremental_minimal_elements`](crate::subsume_index::incremental_minimal_elements),
+/// this does not prune output sets subsumed by a different output set, as those can still be
+/// completed in the same number of steps. Merging counts of equivalent output sets is exact:
+/// a permutation or reflection maps the generalized comparators applicable to one output set
+/// bijectively onto those of the other, and the targets are invariant under both. Applying
+/// `(b, a)` instead of `(a, b)` only swaps two channels, so the counts of standard comparators
+/// are the counts of generalized comparators divided by two per step.
+///
+/// Comparators that never or always swap are skipped. A network containing one is never of
+/// optimal size.
+pub fn next_layer<V: Value>(
+    layer: &[(OutputSet<V>, u128)],
+    reflection: bool,
+) -> Vec<(OutputSet<V>, u128)> {
+    let merged = layer
+        .par_iter()
+        .fold(HashMap::new, |mut merged, (output_set, count)| {
+            let channels = output_set.channels();
+            let implications = output_set.implications();
+            for j in 0..channels {
+                for i in 0..j {
+                    if implications.is_associated(i, j) {
+                        continue;
+                    }
+                    let next = equivalence_class(&output_set.apply_comparator(i, j), reflection);
+                    *merged.entry(next).or_insert(0) += count;
+                }
+            }
+            merged
+        })
+        .reduce(HashMap::new, |mut merged, other| {
+            if merged.len() < other.len() {
+                return other.into_iter().fold(merged, |mut other, (key, count)| {
+                    *other.entry(key).or_insert(0) += count;
+                    other
+                });
+            }
+            for (key, count) in other {
+                *merged.entry(key).or_insert(0) += count;
+            }
+            merged
+        });
+
+    let mut next_layer = merged.into_iter().collect::<Vec<_>>();
+    next_layer.par_sort_unstable();
+    next_layer
+}
+
+/// Counts the networks of minimal size that map `input` to an output set satisfying `target`.
+///
+/// Returns the minimal size and the number of such networks using standard comparators.
+/// `on_layer` is called with the index and the number of equivalence classes of each layer.
+pub fn count_optimal_networks<V: Value>(
+    input: &OutputSet<V>,
+    target: &impl Target,
+    reflection: bool,
+    mut on_layer: impl FnMut(usize, usize),
+) -> (usize, u128) {
+    let mut layer = vec![(equivalence_class(input, reflection), 1)];
+    let mut size = 0;
+
+    loop {
+        on_layer(size, layer.len());
+
+        let count = layer
+            .iter()
+            .filter(|(output_set, _)| target.is_satisfied(output_set))
+            .map(|&(_, count)| count)
+            .sum::<u128>();
+
+        if count > 0 {
+            return (size, count);
+        }
+
+        layer = next_layer(&layer, reflection);
+        size += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{network::Network, target::TargetSpec};
+
+    /// Counts the networks of the given size satisfying the target by trying all of them.
+    fn brute_force(channels: usize, size: usize, target: TargetSpec) -> u128 {
+        let comparators = (0..channels)
+            .flat_map(|j| (0..j).map(move |i| (i, j)))
+            .collect::<Vec<_>>();
+
+        let mut count = 0;
+        let mut indices = vec![0; size];
+        loop {
+            let network = Network::new(
+                channels,
+                indices.iter().map(|&index| comparators[index]).collect(),
+            );
+            count += target.is_satisfied(&network.output_set::<u16>()) as u128;
+
+            match indices
+                .iter()
+                .rposition(|&index| index + 1 < comparators.len())
+            {
+                Some(position) => {
+                    indices[position] += 1;
+                    indices[position + 1..]
+                        .iter_mut()
+                        .for_each(|index| *index = 0);
+                }
+                None => return count,
+            }
+        }
+    }
+
+    #[test]
+    fn agrees_with_brute_force() {
+        for channels in 2..=4 {
+            let input = OutputSet::<u16>::all_values(channels);
+            for &reflection in [false, true].iter() {
+                let (size, count) =
+                    count_optimal_networks(&input, &TargetSpec::Sorting, reflection, |_, _| {});
+                assert_eq!(size, [1, 3, 5][channels - 2]);
+                assert_eq!(count, brute_force(channels, size, TargetSpec::Sorting));
+            }
+        }
+
+        let input = OutputSet::<u16>::all_values(4);
+        for &target in [
+            TargetSpec::TopK(1),
+            TargetSpec::Median,
+            TargetSpec::Selector(2),
+        ]
+        .iter()
+        {
+            let (size, count) = count_optimal_networks(&input, &target, false, |_, _| {});
+            assert_eq!(count, brute_force(4, size, target), "{}", target);
+        }
+    }
+
+    #[test]
+    fn count_sorting_networks() {
+        let input = OutputSet::<u16>::all_values(5);
+        let mut classes = vec![];
+        let (size, count) = count_optimal_networks(&input, &TargetSpec::Sorting, true, |_, len| {
+            classes.push(len)
+        });
+        assert_eq!(size, 9);
+        assert_eq!(count, 149_040);
+        assert_eq!(classes, [1, 1, 2, 5, 11, 15, 16, 11, 6, 3]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::{self, Read, Write};
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    layer_file::{
+        invalid_data, read_u64, read_varint, write_varint, ChecksumReader, ChecksumWriter, Fnv1a,
+    },
+    layer_store::StoreItem,
+    network::Network,
+    output_set::{CVec, MAX_CHANNELS},
+    subsume_index::SubsumeIndexItem,
+};
+
+/// How an output set of a layer was generated from an output set of the previous layer.
+///
+/// Channels are stored as `u8` to keep the recorded layers small.
+#[derive(Clone, Debug, Default)]
+pub struct Provenance {
+    /// Index of the parent output set in the previous layer.
+    pub parent: usize,
+    /// Comparators applied to the parent. These are disjoint when a step adds a whole layer.
+    pub comparators: ArrayVec<[(u8, u8); MAX_CHANNELS]>,
+    /// Permutation applied after the comparators, as passed to
+    /// [`OutputSet::permute_channels`](crate::output_set::OutputSet::permute_channels). Empty
+    /// for the identity.
+    pub perm: ArrayVec<[u8; MAX_CHANNELS]>,
+}
+
+impl Provenance {
+    pub fn new(parent: usize, comparators: &[(usize, usize)], perm: &[usize]) -> Self {
+        Self {
+            parent,
+            comparators: comparators
+                .iter()
+                .map(|&(a, b)| (a as u8, b as u8))
+                .collect(),
+            perm: perm.iter().map(|&channel| channel as u8).collect(),
+        }
+    }
+}
+
+/// The subsuming output set keeps its own provenance, which always is an actual generation step
+/// from an output set of the previous layer. Following those steps never needs the permutation
+/// of a subsumption check.
+impl SubsumeIndexItem for Provenance {
+    fn combine(&mut self, _perm: CVec<usize>, _reflected: bool, _other: Self) {}
+}
+
+impl StoreItem for Provenance {
+    fn write_item<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_provenance(writer, self)
+    }
+
+    fn read_item<R: Read>(reader: &mut R, _channels: usize) -> io::Result<Self> {
+        read_provenance(reader)
+    }
+}
+
+/// Writes the parent, the comparators and the permutation as LEB128 varints and bytes.
+pub(crate) fn write_provenance<W: Write>(
+    writer: &mut W,
+    provenance: &Provenance,
+) -> io::Result<()> {
+    write_varint(writer, provenance.parent as u64)?;
+    write_varint(writer, provenance.comparators.len() as u64)?;
+    for &(a, b) in provenance.comparators.iter() {
+        writer.write_all(&[a, b])?;
+    }
+    write_varint(writer, provenance.perm.len() as u64)?;
+    writer.write_all(&provenance.perm)
+}
+
+pub(crate) fn read_provenance<R: Read>(reader: &mut R) -> io::Result<Provenance> {
+    let mut provenance = Provenance {
+        parent: read_varint(reader)? as usize,
+        ..Provenance::default()
+    };
+
+    let comparators = read_varint(reader)? as usize;
+    if comparators > MAX_CHANNELS {
+        return Err(invalid_data("too many comparators in provenance"));
+    }
+    for _ in 0..comparators {
+        let mut pair = [0; 2];
+        reader.read_exact(&mut pair)?;
+        provenance.comparators.push((pair[0], pair[1]));
+    }
+
+    let channels = read_varint(reader)? as usize;
+    if channels > MAX_CHANNELS {
+        return Err(invalid_data("too many channels in provenance"));
+    }
+    for _ in 0..channels {
+        let mut channel = [0];
+        reader.read_exact(&mut channel)?;
+        provenance.perm.push(channel[0]);
+    }
+
+    Ok(provenance)
+}
+
+/// Writes the number of provenances and for each the parent, the comparators and the
+/// permutation, as LEB128 varints and bytes, followed by a 64-bit FNV-1a checksum.
+pub(crate) fn write_provenances<W: Write>(writer: W, provenances: &[Provenance]) -> io::Result<()> {
+    let mut writer = ChecksumWriter {
+        inner: writer,
+        checksum: Fnv1a::default(),
+    };
+    write_varint(&mut writer, provenances.len() as u64)?;
+    for provenance in provenances {
+        write_provenance(&mut writer, provenance)?;
+    }
+    let checksum = writer.checksum.0;
+    writer.inner.write_all(&checksum.to_le_bytes())
+}
+
+pub(crate) fn read_provenances<R: Read>(reader: R) -> io::Result<Vec<Provenance>> {
+    let mut reader = ChecksumReader {
+        inner: reader,
+        checksum: Fnv1a::default(),
+    };
+
+    let len = read_varint(&mut reader)? as usize;
+    let mut provenances = Vec::with_capacity(len.min(1 << 20));
+    for _ in 0..len {
+        provenances.push(read_provenance(&mut reader)?);
+    }
+
+    let expected = reader.checksum.0;
+    if read_u64(&mut reader.inner)? != expected {
+        return Err(invalid_data("provenance checksum mismatch"));
+    }
+    Ok(provenances)
+}
+
+/// Builds a network that turns the initial output set into the output set at `index` of the last
+/// layer, up to the permutations recorded along the way.
+///
+/// The `k`-th item of `layers` contains the provenance of the output sets in layer `k + 1`. The
+/// layers are visited from the last one backwards, one at a time, so they can be loaded lazily.
+/// The comparators are translated back to the channels of the initial output set, so the result
+/// can contain generalized comparators, see [`Network::untangle`].
+pub fn reconstruct<L: AsRef<[Provenance]>>(
+    channels: usize,
+    layers: impl DoubleEndedIterator<Item = L>,
+    mut index: usize,
+) -> Network {
+    let mut steps = vec![];
+    for layer in layers.rev() {
+        let provenance = layer.as_ref()[index].clone();
+        index = provenance.parent;
+        steps.push(provenance);
+    }
+
+    let mut wires = (0..channels).collect::<Vec<_>>();
+    let mut network = Network::new(channels, vec![]);
+
+    for provenance in steps.into_iter().rev() {
+        for &(a, b) in provenance.comparators.iter() {
+            network.push(wires[a as usize], wires[b as usize]);
+        }
+        if !provenance.perm.is_empty() {
+            wires = provenance
+                .perm
+                .iter()
+                .map(|&channel| wires[channel as usize])
+                .collect();
+        }
+    }
+
+    network
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::output_set::OutputSet;
+
+    #[test]
+    fn reconstruct_path() {
+        let channels = 6;
+        let comparators = [(0, 3), (1, 4), (0, 1), (2, 5), (3, 4), (1, 2), (4, 5)];
+
+        let mut output_set: OutputSet = OutputSet::all_values(channels);
+        let mut layers = vec![];
+
+        for (step, &(a, b)) in comparators.iter().enumerate() {
+            output_set = output_set.apply_comparator(a, b);
+            let provenance = if step % 2 == 0 {
+                let (canonical, perm) = output_set.canonical_form();
+                output_set = canonical;
+                Provenance::new(step, &[(a, b)], &perm)
+            } else {
+                let perm = output_set.order_channels_by_weight();
+                Provenance::new(step, &[(a, b)], &perm)
+            };
+
+            let mut layer = vec![Provenance::default(); step + 2];
+            layer[step + 1] = provenance;
+            layers.push(layer);
+        }
+
+        let network = reconstruct(channels, layers.iter(), comparators.len());
+        assert_eq!(network.size(), comparators.len());
+
+        let mut reached = network.output_set::<u16>();
+        assert_eq!(reached.canonical_form().0, output_set.canonical_form().0);
+
+        let untangled = network.untangle();
+        assert!(untangled.is_standard());
+        reached = untangled.output_set::<u16>();
+        assert_eq!(reached.canonical_form().0, output_set.canonical_form().0);
+    }
+}
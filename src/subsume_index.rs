@@ -1,43 +1,78 @@
+// Pairs are moved through the subsumption checks by value and handed back on failure.
+#![allow(clippy::result_large_err)]
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
 use arrayvec::ArrayVec;
 use crossbeam::queue::{ArrayQueue, SegQueue};
 use parking_lot::Mutex;
 use rayon::{iter::plumbing, prelude::*};
 
 use crate::{
+    layer_store::{LayerStore, StoreItem, WorkQueue},
     matching::Matching,
     output_set::{Abstraction, CVec, OutputSet},
+    value::Value,
 };
 
 pub trait SubsumeIndexItem: Send {
-    fn combine(&mut self, perm: CVec<usize>, other: Self);
+    /// Combines the item of a subsumed output set into the item of the subsuming output set.
+    ///
+    /// The subsumed output set, reflected if `reflected` is set and then permuted by `perm`, is a
+    /// superset of the subsuming output set.
+    fn combine(&mut self, perm: CVec<usize>, reflected: bool, other: Self);
 }
 
+/// Adds up counts, even when the subsumed output set is not equivalent to the subsuming one. For
+/// exact counts of comparator sequences see [`path_count`](crate::path_count).
 impl SubsumeIndexItem for usize {
-    fn combine(&mut self, _perm: CVec<usize>, other: usize) {
+    fn combine(&mut self, _perm: CVec<usize>, _reflected: bool, other: usize) {
         *self += other;
     }
 }
 
 impl SubsumeIndexItem for () {
-    fn combine(&mut self, _perm: CVec<usize>, _other: ()) {}
+    fn combine(&mut self, _perm: CVec<usize>, _reflected: bool, _other: ()) {}
+}
+
+impl<A: SubsumeIndexItem, B: SubsumeIndexItem> SubsumeIndexItem for (A, B) {
+    fn combine(&mut self, perm: CVec<usize>, reflected: bool, other: Self) {
+        self.0.combine(perm.clone(), reflected, other.0);
+        self.1.combine(perm, reflected, other.1);
+    }
+}
+
+/// Symmetries of the sorting network problem that are used for subsumption checks.
+#[derive(Clone, Debug, Default)]
+pub struct Symmetries {
+    /// Also check whether an output set subsumes the reflection of another output set.
+    pub reflection: bool,
+    /// Only use these channel permutations instead of all of them.
+    ///
+    /// This has to be a group and is searched exhaustively, so it should be small.
+    pub permutations: Option<Vec<CVec<usize>>>,
 }
 
 #[derive(Clone, Debug)]
-pub struct AbstractedPair<T> {
-    pub abstraction: Abstraction,
-    pub output_set: OutputSet,
+pub struct AbstractedPair<T, V: Value = u16> {
+    pub abstraction: Abstraction<V>,
+    pub output_set: OutputSet<V>,
     pub item: T,
 }
 
-impl<T> AbstractedPair<T> {
-    pub fn new(output_set: OutputSet, item: T) -> Self {
+impl<T, V: Value> AbstractedPair<T, V> {
+    pub fn new(output_set: OutputSet<V>, item: T) -> Self {
         Self {
             abstraction: output_set.abstraction(),
             output_set,
             item,
         }
     }
-    fn mutex_wrap(self) -> AbstractedPair<Mutex<T>> {
+    fn mutex_wrap(self) -> AbstractedPair<Mutex<T>, V> {
         let Self {
             abstraction,
             output_set,
@@ -51,8 +86,8 @@ impl<T> AbstractedPair<T> {
     }
 }
 
-impl<T> AbstractedPair<Mutex<T>> {
-    fn mutex_unwrap(self) -> AbstractedPair<T> {
+impl<T, V: Value> AbstractedPair<Mutex<T>, V> {
+    fn mutex_unwrap(self) -> AbstractedPair<T, V> {
         let Self {
             abstraction,
             output_set,
@@ -66,21 +101,27 @@ impl<T> AbstractedPair<Mutex<T>> {
     }
 }
 
-pub struct SubsumeIndex<T> {
-    trees: Vec<Node<T>>,
+pub struct SubsumeIndex<T, V: Value = u16> {
+    trees: Vec<Node<T, V>>,
     len: usize,
+    symmetries: Symmetries,
 }
 
-impl<T: SubsumeIndexItem> Default for SubsumeIndex<T> {
+impl<T: SubsumeIndexItem, V: Value> Default for SubsumeIndex<T, V> {
     fn default() -> Self {
+        Self::new(Symmetries::default())
+    }
+}
+
+impl<T: SubsumeIndexItem, V: Value> SubsumeIndex<T, V> {
+    pub fn new(symmetries: Symmetries) -> Self {
         Self {
             trees: Default::default(),
             len: 0,
+            symmetries,
         }
     }
-}
 
-impl<T: SubsumeIndexItem> SubsumeIndex<T> {
     pub fn len(&self) -> usize {
         self.len
     }
@@ -89,7 +130,7 @@ impl<T: SubsumeIndexItem> SubsumeIndex<T> {
         self.trees.is_empty()
     }
 
-    pub fn insert(&mut self, pair: AbstractedPair<T>) {
+    pub fn insert(&mut self, pair: AbstractedPair<T, V>) {
         self.combine_with_subsuming(pair).unwrap_or_else(|pair| {
             self.len += 1;
             self.trees.push(Node::Leaf(pair.mutex_wrap()));
@@ -103,10 +144,10 @@ impl<T: SubsumeIndexItem> SubsumeIndex<T> {
 
     fn combine_with_subsuming(
         &mut self,
-        mut pair: AbstractedPair<T>,
-    ) -> Result<(), AbstractedPair<T>> {
+        mut pair: AbstractedPair<T, V>,
+    ) -> Result<(), AbstractedPair<T, V>> {
         for tree in self.trees.iter_mut() {
-            match tree.combine_with_subsuming(pair) {
+            match tree.combine_with_subsuming(pair, &self.symmetries) {
                 Ok(()) => return Ok(()),
                 Err(returned_pair) => pair = returned_pair,
             }
@@ -130,12 +171,12 @@ impl<T: SubsumeIndexItem> SubsumeIndex<T> {
             self.len -= second_last_tree.len();
 
             let mut pairs = vec![];
-            second_last_tree.drain_using(
-                &mut |pair| match last_tree.combine_with_subsuming(pair) {
-                    Ok(()) => (),
-                    Err(returned_pair) => pairs.push(returned_pair),
-                },
-            );
+            second_last_tree.drain_using(&mut |pair| match last_tree
+                .combine_with_subsuming(pair, &self.symmetries)
+            {
+                Ok(()) => (),
+                Err(returned_pair) => pairs.push(returned_pair),
+            });
 
             last_tree.drain_using(&mut |pair| pairs.push(pair));
 
@@ -147,32 +188,34 @@ impl<T: SubsumeIndexItem> SubsumeIndex<T> {
         }
     }
 
-    pub fn drain_using(self, mut target: impl FnMut(AbstractedPair<T>)) {
+    pub fn drain_using(self, mut target: impl FnMut(AbstractedPair<T, V>)) {
         for tree in self.trees {
             tree.drain_using(&mut target)
         }
     }
 }
 
-pub enum Node<T> {
-    Leaf(AbstractedPair<Mutex<T>>),
+pub enum Node<T, V: Value = u16> {
+    Leaf(AbstractedPair<Mutex<T>, V>),
     Inner {
-        abstraction: Abstraction,
-        children: Box<[Node<T>; 2]>,
+        abstraction: Abstraction<V>,
+        children: Box<[Node<T, V>; 2]>,
         len: usize,
     },
 }
 
-impl<T: SubsumeIndexItem> Node<T> {
-    pub fn new(mut items: Vec<AbstractedPair<T>>) -> Self {
+impl<T: SubsumeIndexItem, V: Value> Node<T, V> {
+    pub fn new(mut items: Vec<AbstractedPair<T, V>>) -> Self {
         assert!(!items.is_empty());
 
         while let Some(last) = items.pop() {
             if let Some(second_last) = items.last_mut() {
                 if second_last.output_set == last.output_set {
-                    second_last
-                        .item
-                        .combine((0..last.output_set.channels()).collect(), last.item);
+                    second_last.item.combine(
+                        (0..last.output_set.channels()).collect(),
+                        false,
+                        last.item,
+                    );
                     continue;
                 }
             }
@@ -209,6 +252,8 @@ impl<T: SubsumeIndexItem> Node<T> {
         }
     }
 
+    // A node always contains at least one output set.
+    #[allow(clippy::len_without_is_empty, clippy::match_ref_pats)]
     pub fn len(&self) -> usize {
         match self {
             &Node::Leaf(..) => 1,
@@ -216,14 +261,14 @@ impl<T: SubsumeIndexItem> Node<T> {
         }
     }
 
-    fn abstraction(&self) -> &Abstraction {
+    fn abstraction(&self) -> &Abstraction<V> {
         match self {
             Node::Leaf(pair) => &pair.abstraction,
             Node::Inner { abstraction, .. } => abstraction,
         }
     }
 
-    fn drain_using(self, target: &mut impl FnMut(AbstractedPair<T>)) {
+    fn drain_using(self, target: &mut impl FnMut(AbstractedPair<T, V>)) {
         match self {
             Node::Leaf(pair) => target(pair.mutex_unwrap()),
             Node::Inner { children, .. } => {
@@ -234,16 +279,97 @@ impl<T: SubsumeIndexItem> Node<T> {
         }
     }
 
-    pub fn combine_with_subsuming(&self, pair: AbstractedPair<T>) -> Result<(), AbstractedPair<T>> {
+    pub fn combine_with_subsuming(
+        &self,
+        pair: AbstractedPair<T, V>,
+        symmetries: &Symmetries,
+    ) -> Result<(), AbstractedPair<T, V>> {
+        let pair = match self.combine_with_subsuming_permuted(pair, false, symmetries) {
+            Err(pair) if symmetries.reflection => pair,
+            result => return result,
+        };
+
+        let AbstractedPair {
+            abstraction,
+            output_set,
+            item,
+        } = pair;
+
+        let reflected_pair = AbstractedPair {
+            abstraction: abstraction.reflect(),
+            output_set: output_set.reflect(),
+            item,
+        };
+
+        self.combine_with_subsuming_permuted(reflected_pair, true, symmetries)
+            .map_err(|reflected_pair| AbstractedPair {
+                abstraction,
+                output_set,
+                item: reflected_pair.item,
+            })
+    }
+
+    fn combine_with_subsuming_permuted(
+        &self,
+        pair: AbstractedPair<T, V>,
+        reflected: bool,
+        symmetries: &Symmetries,
+    ) -> Result<(), AbstractedPair<T, V>> {
         let channels = pair.output_set.channels();
-        self.combine_with_subsuming_rec(pair, Matching::new(channels))
+        let identity = (0..channels).collect::<CVec<_>>();
+
+        let permutations = match &symmetries.permutations {
+            None => {
+                return self.combine_with_subsuming_rec(
+                    pair,
+                    reflected,
+                    Matching::new(channels),
+                    &identity,
+                )
+            }
+            Some(permutations) => permutations,
+        };
+
+        let mut fixed = Matching::new(channels);
+        for channel in 0..channels {
+            let incomplete = fixed.select(channel, channel);
+            assert!(!incomplete);
+        }
+
+        let AbstractedPair {
+            abstraction,
+            output_set,
+            mut item,
+        } = pair;
+
+        for perm in permutations.iter() {
+            let mut permuted = output_set.clone();
+            permuted.permute_channels(perm.clone());
+            match self.combine_with_subsuming_rec(
+                AbstractedPair::new(permuted, item),
+                reflected,
+                fixed.clone(),
+                perm,
+            ) {
+                Ok(()) => return Ok(()),
+                Err(returned_pair) => item = returned_pair.item,
+            }
+        }
+
+        Err(AbstractedPair {
+            abstraction,
+            output_set,
+            item,
+        })
     }
 
     fn combine_with_subsuming_rec(
         &self,
-        pair: AbstractedPair<T>,
+        pair: AbstractedPair<T, V>,
+        reflected: bool,
         mut matching: Matching,
-    ) -> Result<(), AbstractedPair<T>> {
+        perm: &CVec<usize>,
+    ) -> Result<(), AbstractedPair<T, V>> {
         let node_abstraction = self.abstraction();
 
         if matching.filter(|node_channel, pair_channel| {
@@ -253,21 +379,23 @@ impl<T: SubsumeIndexItem> Node<T> {
         }
         match self {
             Node::Leaf(node_pair) => {
-                let perm = (0..node_pair.output_set.channels()).collect();
-                Self::combine_permuted(node_pair, pair, perm, matching)
+                Self::combine_permuted(node_pair, pair, perm.clone(), reflected, matching)
             }
             Node::Inner { children, .. } => children[0]
-                .combine_with_subsuming_rec(pair, matching.clone())
-                .or_else(|pair| children[1].combine_with_subsuming_rec(pair, matching)),
+                .combine_with_subsuming_rec(pair, reflected, matching.clone(), perm)
+                .or_else(|pair| {
+                    children[1].combine_with_subsuming_rec(pair, reflected, matching, perm)
+                }),
         }
     }
 
     fn combine_permuted(
-        node_pair: &AbstractedPair<Mutex<T>>,
-        mut pair: AbstractedPair<T>,
+        node_pair: &AbstractedPair<Mutex<T>, V>,
+        mut pair: AbstractedPair<T, V>,
         mut perm: CVec<usize>,
+        reflected: bool,
         mut matching: Matching,
-    ) -> Result<(), AbstractedPair<T>> {
+    ) -> Result<(), AbstractedPair<T, V>> {
         let channels = node_pair.output_set.channels();
 
         let mut unique_matched = 0;
@@ -290,7 +418,7 @@ impl<T: SubsumeIndexItem> Node<T> {
         if unique_matched == channels {
             if node_pair.output_set.subsumes(&pair.output_set) {
                 let mut item = node_pair.item.lock();
-                item.combine(perm, pair.item);
+                item.combine(perm, reflected, pair.item);
                 return Ok(());
             }
         } else {
@@ -310,7 +438,13 @@ impl<T: SubsumeIndexItem> Node<T> {
                 for channel_b in 0..channels {
                     let mut next_matching = matching.clone();
                     if !next_matching.select(channel_a, channel_b) {
-                        match Self::combine_permuted(node_pair, pair, perm.clone(), next_matching) {
+                        match Self::combine_permuted(
+                            node_pair,
+                            pair,
+                            perm.clone(),
+                            reflected,
+                            next_matching,
+                        ) {
                             Ok(perm) => return Ok(perm),
                             Err(returned_pair) => pair = returned_pair,
                         }
@@ -320,7 +454,13 @@ impl<T: SubsumeIndexItem> Node<T> {
                 for channel_a in 0..channels {
                     let mut next_matching = matching.clone();
                     if !next_matching.select(channel_a, channel_b) {
-                        match Self::combine_permuted(node_pair, pair, perm.clone(), next_matching) {
+                        match Self::combine_permuted(
+                            node_pair,
+                            pair,
+                            perm.clone(),
+                            reflected,
+                            next_matching,
+                        ) {
                             Ok(perm) => return Ok(perm),
                             Err(returned_pair) => pair = returned_pair,
                         }
@@ -334,15 +474,15 @@ impl<T: SubsumeIndexItem> Node<T> {
         Err(pair)
     }
 
-    pub fn minimal_elements(self) -> Vec<AbstractedPair<T>> {
+    pub fn minimal_elements(self, symmetries: &Symmetries) -> Vec<AbstractedPair<T, V>> {
         match self {
             Node::Inner { children, .. } => {
                 let [child_0, child_1] = *children;
 
-                let child_0 = Self::new(child_0.minimal_elements());
+                let child_0 = Self::new(child_0.minimal_elements(symmetries));
 
                 let child_1_pairs = child_1
-                    .flat_map(|pair| child_0.combine_with_subsuming(pair).err())
+                    .flat_map(|pair| child_0.combine_with_subsuming(pair, symmetries).err())
                     .collect::<Vec<_>>();
 
                 if child_1_pairs.is_empty() {
@@ -352,10 +492,39 @@ impl<T: SubsumeIndexItem> Node<T> {
                 let child_1 = Self::new(child_1_pairs);
 
                 let mut child_0_pairs = child_0
-                    .flat_map(|pair| child_1.combine_with_subsuming(pair).err())
+                    .flat_map(|pair| child_1.combine_with_subsuming(pair, symmetries).err())
+                    .collect::<Vec<_>>();
+
+                child_0_pairs.extend(child_1.minimal_elements(symmetries));
+                child_0_pairs
+            }
+            Node::Leaf(pair) => vec![pair.mutex_unwrap()],
+        }
+    }
+
+    /// Like [`minimal_elements`](Node::minimal_elements) for output sets that all have the same
+    /// number of values.
+    ///
+    /// Such output sets only subsume each other when they are equivalent, which is symmetric, so
+    /// the pairs of the first child do not have to be checked against the second child.
+    fn minimal_elements_of_equal_size(self, symmetries: &Symmetries) -> Vec<AbstractedPair<T, V>> {
+        match self {
+            Node::Inner { children, .. } => {
+                let [child_0, child_1] = *children;
+
+                let child_0 = Self::new(child_0.minimal_elements_of_equal_size(symmetries));
+
+                let child_1_pairs = child_1
+                    .flat_map(|pair| child_0.combine_with_subsuming(pair, symmetries).err())
                     .collect::<Vec<_>>();
 
-                child_0_pairs.extend(child_1.minimal_elements());
+                let mut child_0_pairs = child_0.collect::<Vec<_>>();
+
+                if !child_1_pairs.is_empty() {
+                    child_0_pairs.extend(
+                        Self::new(child_1_pairs).minimal_elements_of_equal_size(symmetries),
+                    );
+                }
                 child_0_pairs
             }
             Node::Leaf(pair) => vec![pair.mutex_unwrap()],
@@ -363,34 +532,220 @@ impl<T: SubsumeIndexItem> Node<T> {
     }
 }
 
-pub fn incremental_minimal_elements<T, In, G>(
+pub fn incremental_minimal_elements<T, V, In, G>(
     inputs: Vec<In>,
+    symmetries: &Symmetries,
     generator: G,
-) -> Vec<AbstractedPair<T>>
+) -> Vec<AbstractedPair<T, V>>
 where
     T: SubsumeIndexItem,
-    G: Sync + Fn(In) -> Vec<AbstractedPair<T>>,
+    V: Value,
+    G: Sync + Fn(In) -> Vec<AbstractedPair<T, V>>,
     In: Send,
 {
-    let mut node: Option<Node<T>> = None;
-    let mut chunk_size = 1024;
+    resume_minimal_elements(
+        IncrementalState::new(inputs),
+        symmetries,
+        generator,
+        None::<(Duration, fn(&IncrementalState<T, V, In>))>,
+        &AtomicBool::new(false),
+    )
+    .unwrap_or_else(|_| unreachable!())
+}
+
+/// Computes the same minimal elements as [`incremental_minimal_elements`] by processing the
+/// generated output sets in buckets of equal size.
+///
+/// An output set can only be subsumed by an output set with at most as many values. The buckets
+/// are processed from the smallest size up, and each bucket is only checked against the indices of
+/// the smaller buckets, which are final by then. This avoids checking the minimal elements found so
+/// far against newly generated output sets and rebuilding their index. All generated output sets
+/// are kept in memory until the smallest bucket is done, though.
+pub fn stratified_minimal_elements<T, V, In, G>(
+    inputs: Vec<In>,
+    symmetries: &Symmetries,
+    generator: G,
+) -> Vec<AbstractedPair<T, V>>
+where
+    T: SubsumeIndexItem,
+    V: Value,
+    G: Sync + Fn(In) -> Vec<AbstractedPair<T, V>>,
+    In: Send,
+{
+    let generated = inputs
+        .into_par_iter()
+        .flat_map(&generator)
+        .collect::<Vec<_>>();
+
+    let mut buckets = BTreeMap::<usize, Vec<_>>::new();
+    for pair in generated {
+        buckets
+            .entry(pair.output_set.values().len())
+            .or_default()
+            .push(pair);
+    }
+
+    let mut finished: Vec<Node<T, V>> = vec![];
+
+    for (_, bucket) in buckets {
+        let remaining = bucket
+            .into_par_iter()
+            .filter_map(|mut pair| {
+                // Starting with the smallest output sets turned out faster than starting with
+                // nearby sizes.
+                for node in finished.iter() {
+                    match node.combine_with_subsuming(pair, symmetries) {
+                        Ok(()) => return None,
+                        Err(returned_pair) => pair = returned_pair,
+                    }
+                }
+                Some(pair)
+            })
+            .collect::<Vec<_>>();
+
+        if !remaining.is_empty() {
+            let minimal = Node::new(remaining).minimal_elements_of_equal_size(symmetries);
+            finished.push(Node::new(minimal));
+        }
+    }
+
+    finished.into_par_iter().flatten().collect()
+}
+
+/// State of [`incremental_minimal_elements`] between two chunks of generated output sets.
+pub struct IncrementalState<T, V: Value, In> {
+    /// Minimal elements of the output sets added so far.
+    pub minimal: Vec<AbstractedPair<T, V>>,
+    /// Generated output sets that still have to be added.
+    pub spilled: Vec<AbstractedPair<T, V>>,
+    /// Inputs that still have to be passed to the generator.
+    pub inputs: Vec<In>,
+    /// Number of output sets to collect before adding them in one go.
+    pub chunk_size: usize,
+}
+
+impl<T, V: Value, In> IncrementalState<T, V, In> {
+    pub fn new(inputs: Vec<In>) -> Self {
+        Self {
+            minimal: vec![],
+            spilled: vec![],
+            inputs,
+            chunk_size: 1024,
+        }
+    }
+}
+
+/// Continues [`incremental_minimal_elements`] from a saved state.
+///
+/// If `save` is given, the current chunk is cut short whenever the given interval has passed since
+/// the last save, and the state is passed to the save function before continuing.
+///
+/// Once `stop` is set, no further inputs or spilled output sets are taken, the output sets
+/// generated so far are added and the remaining state is returned as an error.
+pub fn resume_minimal_elements<T, V, In, G, S>(
+    state: IncrementalState<T, V, In>,
+    symmetries: &Symmetries,
+    generator: G,
+    save: Option<(Duration, S)>,
+    stop: &AtomicBool,
+) -> Result<Vec<AbstractedPair<T, V>>, IncrementalState<T, V, In>>
+where
+    T: SubsumeIndexItem,
+    V: Value,
+    G: Sync + Fn(In) -> Vec<AbstractedPair<T, V>>,
+    In: Send,
+    S: FnMut(&IncrementalState<T, V, In>),
+{
+    minimal_elements_with_queues(
+        state,
+        (SegQueue::new(), SegQueue::new()),
+        symmetries,
+        generator,
+        save,
+        stop,
+    )
+}
 
-    let input_queue = SegQueue::<In>::new();
-    let spill_queue = SegQueue::<AbstractedPair<T>>::new();
+/// Like [`resume_minimal_elements`], but keeps the remaining inputs and the spilled output sets in
+/// temporary files of `store`, so that only the index of minimal elements stays in memory.
+///
+/// Saving or stopping reads the remaining inputs and spilled output sets back into memory.
+pub fn resume_minimal_elements_in<T, V, In, G, S>(
+    store: &LayerStore,
+    state: IncrementalState<T, V, In>,
+    symmetries: &Symmetries,
+    generator: G,
+    save: Option<(Duration, S)>,
+    stop: &AtomicBool,
+) -> Result<Vec<AbstractedPair<T, V>>, IncrementalState<T, V, In>>
+where
+    T: SubsumeIndexItem + StoreItem,
+    V: Value,
+    G: Sync + Fn(In) -> Vec<AbstractedPair<T, V>>,
+    In: Send + StoreItem,
+    S: FnMut(&IncrementalState<T, V, In>),
+{
+    minimal_elements_with_queues(
+        state,
+        (store.queue(), store.queue()),
+        symmetries,
+        generator,
+        save,
+        stop,
+    )
+}
+
+fn minimal_elements_with_queues<T, V, In, G, S, QI, QS>(
+    state: IncrementalState<T, V, In>,
+    (input_queue, spill_queue): (QI, QS),
+    symmetries: &Symmetries,
+    generator: G,
+    mut save: Option<(Duration, S)>,
+    stop: &AtomicBool,
+) -> Result<Vec<AbstractedPair<T, V>>, IncrementalState<T, V, In>>
+where
+    T: SubsumeIndexItem,
+    V: Value,
+    G: Sync + Fn(In) -> Vec<AbstractedPair<T, V>>,
+    In: Send,
+    S: FnMut(&IncrementalState<T, V, In>),
+    QI: WorkQueue<In>,
+    QS: WorkQueue<AbstractedPair<T, V>>,
+{
+    let mut node: Option<Node<T, V>> = None;
+    let mut chunk_size = state.chunk_size;
 
-    for input in inputs {
+    if !state.minimal.is_empty() {
+        node = Some(Node::new(state.minimal));
+    }
+    for input in state.inputs {
         input_queue.push(input);
     }
+    for pair in state.spilled {
+        spill_queue.push(pair);
+    }
+
+    let mut deadline = save
+        .as_ref()
+        .map(|(interval, _)| Instant::now() + *interval);
 
     while !input_queue.is_empty() || !spill_queue.is_empty() {
-        let output_queue = ArrayQueue::<AbstractedPair<T>>::new(chunk_size);
+        let output_queue = ArrayQueue::<AbstractedPair<T, V>>::new(chunk_size);
+
+        let is_stopped = || stop.load(Ordering::Relaxed);
+        let is_due = || is_stopped() || deadline.is_some_and(|deadline| Instant::now() >= deadline);
 
         rayon::scope(|s| {
             for _ in 0..rayon::current_num_threads() {
                 s.spawn(|_| {
-                    while let Ok(pair) = spill_queue.pop() {
+                    while !is_stopped() {
+                        let pair = match spill_queue.pop() {
+                            Some(pair) => pair,
+                            None => break,
+                        };
+
                         let res = if let Some(node) = &node {
-                            node.combine_with_subsuming(pair)
+                            node.combine_with_subsuming(pair, symmetries)
                         } else {
                             Err(pair)
                         };
@@ -401,13 +756,17 @@ where
                                 break;
                             }
                         }
+
+                        if is_due() {
+                            break;
+                        }
                     }
 
-                    while !output_queue.is_full() {
-                        if let Ok(item) = input_queue.pop() {
+                    while !output_queue.is_full() && !is_stopped() {
+                        if let Some(item) = input_queue.pop() {
                             for pair in generator(item) {
                                 let res = if let Some(node) = &node {
-                                    node.combine_with_subsuming(pair)
+                                    node.combine_with_subsuming(pair, symmetries)
                                 } else {
                                     Err(pair)
                                 };
@@ -421,46 +780,89 @@ where
                         } else {
                             break;
                         }
+
+                        if is_due() {
+                            break;
+                        }
                     }
                 })
             }
         });
 
+        let cut_short = is_due();
+
         let mut outputs = Vec::with_capacity(chunk_size);
 
         while let Ok(pair) = output_queue.pop() {
             outputs.push(pair);
         }
 
-        if outputs.is_empty() {
-            continue;
+        if !outputs.is_empty() {
+            let node_1 = Node::new(outputs);
+
+            if let Some(node_0) = node {
+                let mut node_0_pairs = node_0
+                    .flat_map(|pair| node_1.combine_with_subsuming(pair, symmetries).err())
+                    .collect::<Vec<_>>();
+
+                node_0_pairs.extend(node_1.minimal_elements(symmetries));
+
+                node = Some(Node::new(node_0_pairs));
+            } else {
+                node = Some(Node::new(node_1.minimal_elements(symmetries)));
+            }
+
+            if !cut_short {
+                chunk_size *= 2;
+            }
         }
 
-        let node_1 = Node::new(outputs);
+        if cut_short && (save.is_some() || is_stopped()) {
+            let mut state = IncrementalState {
+                minimal: node.take().into_par_iter().flatten().collect(),
+                spilled: drain_queue(&spill_queue),
+                inputs: drain_queue(&input_queue),
+                chunk_size,
+            };
 
-        if let Some(node_0) = node {
-            let mut node_0_pairs = node_0
-                .flat_map(|pair| node_1.combine_with_subsuming(pair).err())
-                .collect::<Vec<_>>();
+            let (interval, save) = match (&mut save, is_stopped()) {
+                (Some(save), false) => save,
+                _ => return Err(state),
+            };
 
-            node_0_pairs.extend(node_1.minimal_elements());
+            save(&state);
 
-            node = Some(Node::new(node_0_pairs));
-        } else {
-            node = Some(Node::new(node_1.minimal_elements()));
+            if !state.minimal.is_empty() {
+                node = Some(Node::new(std::mem::take(&mut state.minimal)));
+            }
+            for input in state.inputs {
+                input_queue.push(input);
+            }
+            for pair in state.spilled {
+                spill_queue.push(pair);
+            }
+
+            deadline = Some(Instant::now() + *interval);
         }
-        chunk_size *= 2;
     }
 
-    node.into_par_iter().flatten().collect::<Vec<_>>()
+    Ok(node.into_par_iter().flatten().collect::<Vec<_>>())
+}
+
+fn drain_queue<T>(queue: &impl WorkQueue<T>) -> Vec<T> {
+    let mut items = vec![];
+    while let Some(item) = queue.pop() {
+        items.push(item);
+    }
+    items
 }
 
-pub struct NodeIter<T> {
-    nodes: Vec<Node<T>>,
+pub struct NodeIter<T, V: Value = u16> {
+    nodes: Vec<Node<T, V>>,
 }
 
-impl<T: SubsumeIndexItem> Iterator for NodeIter<T> {
-    type Item = AbstractedPair<T>;
+impl<T: SubsumeIndexItem, V: Value> Iterator for NodeIter<T, V> {
+    type Item = AbstractedPair<T, V>;
 
     fn next(&mut self) -> Option<Self::Item> {
         while let Some(node) = self.nodes.pop() {
@@ -474,17 +876,17 @@ impl<T: SubsumeIndexItem> Iterator for NodeIter<T> {
     }
 }
 
-impl<T: SubsumeIndexItem> IntoIterator for Node<T> {
-    type Item = AbstractedPair<T>;
-    type IntoIter = NodeIter<T>;
+impl<T: SubsumeIndexItem, V: Value> IntoIterator for Node<T, V> {
+    type Item = AbstractedPair<T, V>;
+    type IntoIter = NodeIter<T, V>;
 
     fn into_iter(self) -> Self::IntoIter {
         NodeIter { nodes: vec![self] }
     }
 }
 
-impl<T: SubsumeIndexItem> plumbing::UnindexedProducer for Node<T> {
-    type Item = AbstractedPair<T>;
+impl<T: SubsumeIndexItem, V: Value> plumbing::UnindexedProducer for Node<T, V> {
+    type Item = AbstractedPair<T, V>;
 
     fn split(self) -> (Self, Option<Self>) {
         match self {
@@ -496,6 +898,7 @@ impl<T: SubsumeIndexItem> plumbing::UnindexedProducer for Node<T> {
         }
     }
 
+    #[allow(clippy::useless_conversion)]
     fn fold_with<F>(self, folder: F) -> F
     where
         F: plumbing::Folder<Self::Item>,
@@ -504,8 +907,8 @@ impl<T: SubsumeIndexItem> plumbing::UnindexedProducer for Node<T> {
     }
 }
 
-impl<T: SubsumeIndexItem> ParallelIterator for Node<T> {
-    type Item = AbstractedPair<T>;
+impl<T: SubsumeIndexItem, V: Value> ParallelIterator for Node<T, V> {
+    type Item = AbstractedPair<T, V>;
 
     fn drive_unindexed<C>(self, consumer: C) -> C::Result
     where
@@ -519,7 +922,7 @@ impl<T: SubsumeIndexItem> ParallelIterator for Node<T> {
 mod test {
     use super::*;
 
-    fn gen_some_output_sets(channels: usize) -> Vec<OutputSet> {
+    fn gen_some_output_sets<V: Value>(channels: usize) -> Vec<OutputSet<V>> {
         let all_values = OutputSet::all_values(channels);
 
         let mut some_outputs = vec![];
@@ -545,12 +948,41 @@ mod test {
         some_outputs
     }
 
+    /// Number of channels of [`some_inputs`].
+    const CHANNELS: usize = 6;
+
+    /// A sample of the output sets reachable with three comparators.
+    fn some_inputs() -> Vec<OutputSet> {
+        gen_some_output_sets(CHANNELS)
+            .into_iter()
+            .step_by(40)
+            .collect()
+    }
+
+    /// Generator applying every comparator to an output set and ordering the channels of the
+    /// results by weight, with a copy of `item` for each result.
+    fn all_comparators_generator<T: Copy + Sync>(
+        item: T,
+    ) -> impl Copy + Sync + Fn(OutputSet) -> Vec<AbstractedPair<T, u16>> {
+        move |output_set: OutputSet| {
+            let channels = output_set.channels();
+            (0..channels)
+                .flat_map(|j| (0..j).map(move |i| (i, j)))
+                .map(|(i, j)| {
+                    let mut next = output_set.apply_comparator(i, j);
+                    next.order_channels_by_weight();
+                    AbstractedPair::new(next, item)
+                })
+                .collect::<Vec<_>>()
+        }
+    }
+
     #[test]
     fn build_index() {
         crate::logging::setup();
 
         for (i, &expected) in [1, 4, 6, 7, 7, 7].iter().enumerate() {
-            let some_output_sets = gen_some_output_sets(i + 3);
+            let some_output_sets = gen_some_output_sets::<u16>(i + 3);
 
             log::info!("initial output sets: {}", some_output_sets.len());
 
@@ -559,7 +991,7 @@ mod test {
                 .map(|output_set| AbstractedPair::new(output_set, 1))
                 .collect();
 
-            let minimal = Node::new(abstracted_pairs).minimal_elements();
+            let minimal = Node::new(abstracted_pairs).minimal_elements(&Symmetries::default());
 
             log::info!("minimal output sets: {}", minimal.len());
             assert_eq!(minimal.len(), expected);
@@ -573,4 +1005,268 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn build_index_reflection() {
+        crate::logging::setup();
+
+        let symmetries = Symmetries {
+            reflection: true,
+            ..Symmetries::default()
+        };
+
+        for (i, &expected) in [1, 3, 4, 5, 5, 5].iter().enumerate() {
+            let some_output_sets = gen_some_output_sets::<u16>(i + 3);
+
+            let abstracted_pairs = some_output_sets
+                .into_iter()
+                .map(|output_set| AbstractedPair::new(output_set, 1))
+                .collect();
+
+            let minimal = Node::new(abstracted_pairs).minimal_elements(&symmetries);
+
+            log::info!("minimal output sets: {}", minimal.len());
+            assert_eq!(minimal.len(), expected);
+
+            let index = Node::new(minimal);
+
+            for output_set in gen_some_output_sets::<u16>(i + 3) {
+                let reflected_pair = AbstractedPair::new(output_set.reflect(), 0);
+                assert!(index
+                    .combine_with_subsuming(reflected_pair, &symmetries)
+                    .is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn resume_incremental() {
+        crate::logging::setup();
+
+        let inputs = some_inputs();
+        let generator = all_comparators_generator(());
+
+        let symmetries = Symmetries::default();
+
+        let expected = incremental_minimal_elements(inputs.clone(), &symmetries, generator);
+
+        let mut saved = vec![];
+        let saving = resume_minimal_elements(
+            IncrementalState::new(inputs),
+            &symmetries,
+            generator,
+            Some((
+                Duration::from_secs(0),
+                |state: &IncrementalState<_, _, _>| {
+                    saved.push(IncrementalState {
+                        minimal: state.minimal.clone(),
+                        spilled: state.spilled.clone(),
+                        inputs: state.inputs.clone(),
+                        chunk_size: state.chunk_size,
+                    })
+                },
+            )),
+            &AtomicBool::new(false),
+        )
+        .unwrap_or_else(|_| unreachable!());
+        assert_eq!(saving.len(), expected.len());
+        assert!(saved.len() > 2);
+
+        let index = Node::new(expected);
+        let len = index.len();
+
+        for state in saved.into_iter().step_by(9) {
+            let resumed = resume_minimal_elements(
+                state,
+                &symmetries,
+                generator,
+                None::<(Duration, fn(&IncrementalState<(), u16, OutputSet>))>,
+                &AtomicBool::new(false),
+            )
+            .unwrap_or_else(|_| unreachable!());
+            assert_eq!(resumed.len(), len);
+            for pair in resumed {
+                assert!(index.combine_with_subsuming(pair, &symmetries).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn stratified() {
+        crate::logging::setup();
+
+        let inputs = some_inputs();
+        let generator = all_comparators_generator(1);
+
+        for &reflection in [false, true].iter() {
+            let symmetries = Symmetries {
+                reflection,
+                ..Symmetries::default()
+            };
+
+            let expected = incremental_minimal_elements(inputs.clone(), &symmetries, generator);
+            let stratified = stratified_minimal_elements(inputs.clone(), &symmetries, generator);
+
+            assert_eq!(stratified.len(), expected.len());
+            assert_eq!(
+                stratified.iter().map(|pair| pair.item).sum::<usize>(),
+                inputs.len() * CHANNELS * (CHANNELS - 1) / 2
+            );
+
+            let index = Node::new(expected);
+            for pair in stratified {
+                assert!(index.combine_with_subsuming(pair, &symmetries).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn stop_incremental() {
+        crate::logging::setup();
+
+        let inputs = some_inputs();
+        let input_count = inputs.len();
+
+        let stop = AtomicBool::new(false);
+        let generated = std::sync::atomic::AtomicUsize::new(0);
+
+        let generator = |output_set: OutputSet| {
+            if generated.fetch_add(1, Ordering::Relaxed) + 1 == input_count / 2 {
+                stop.store(true, Ordering::Relaxed);
+            }
+            all_comparators_generator(())(output_set)
+        };
+
+        let symmetries = Symmetries::default();
+        let no_save = None::<(Duration, fn(&IncrementalState<(), u16, OutputSet>))>;
+
+        let state = match resume_minimal_elements(
+            IncrementalState::new(inputs.clone()),
+            &symmetries,
+            generator,
+            no_save,
+            &stop,
+        ) {
+            Ok(_) => panic!("generation was not stopped"),
+            Err(state) => state,
+        };
+        assert_eq!(
+            state.inputs.len(),
+            input_count - generated.load(Ordering::Relaxed)
+        );
+        assert!(!state.inputs.is_empty());
+
+        stop.store(false, Ordering::Relaxed);
+        let resumed = resume_minimal_elements(state, &symmetries, generator, no_save, &stop)
+            .unwrap_or_else(|_| unreachable!());
+
+        let expected = incremental_minimal_elements(inputs, &symmetries, generator);
+        assert_eq!(resumed.len(), expected.len());
+
+        let index = Node::new(expected);
+        for pair in resumed {
+            assert!(index.combine_with_subsuming(pair, &symmetries).is_ok());
+        }
+    }
+
+    #[test]
+    fn incremental_on_disk() {
+        crate::logging::setup();
+
+        let inputs = some_inputs();
+        let generator = all_comparators_generator(());
+
+        let symmetries = Symmetries::default();
+
+        let expected = incremental_minimal_elements(inputs.clone(), &symmetries, generator);
+
+        let dir =
+            std::env::temp_dir().join(format!("sortnetopt-incremental-{}", std::process::id()));
+        let store = LayerStore::new(&dir, CHANNELS, 5).unwrap();
+
+        let minimal = resume_minimal_elements_in(
+            &store,
+            IncrementalState::new(inputs),
+            &symmetries,
+            generator,
+            None::<(Duration, fn(&IncrementalState<(), u16, OutputSet>))>,
+            &AtomicBool::new(false),
+        )
+        .unwrap_or_else(|_| unreachable!());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(minimal.len(), expected.len());
+        let index = Node::new(expected);
+        for pair in minimal {
+            assert!(index.combine_with_subsuming(pair, &symmetries).is_ok());
+        }
+    }
+
+    #[test]
+    fn build_index_wide() {
+        crate::logging::setup();
+
+        for (i, &expected) in [1, 4, 6, 7].iter().enumerate() {
+            let abstracted_pairs = gen_some_output_sets::<u32>(i + 3)
+                .into_iter()
+                .map(|output_set| AbstractedPair::new(output_set, 1))
+                .collect();
+
+            let minimal = Node::new(abstracted_pairs).minimal_elements(&Symmetries::default());
+
+            assert_eq!(minimal.len(), expected);
+        }
+    }
+
+    #[test]
+    fn build_index_restricted_permutations() {
+        crate::logging::setup();
+
+        let channels = 5;
+
+        let mut all_perms: Vec<CVec<usize>> = vec![(0..channels).collect()];
+        for i in 1..channels {
+            all_perms = all_perms
+                .into_iter()
+                .flat_map(|perm| {
+                    (0..=i).map(move |j| {
+                        let mut perm = perm.clone();
+                        perm.swap(i, j);
+                        perm
+                    })
+                })
+                .collect();
+        }
+
+        let minimal_len = |permutations: Option<Vec<CVec<usize>>>| {
+            let symmetries = Symmetries {
+                permutations,
+                ..Symmetries::default()
+            };
+            let abstracted_pairs = gen_some_output_sets::<u16>(channels)
+                .into_iter()
+                .map(|output_set| AbstractedPair::new(output_set, 1))
+                .collect();
+            Node::new(abstracted_pairs)
+                .minimal_elements(&symmetries)
+                .len()
+        };
+
+        let mut output_sets = gen_some_output_sets::<u16>(channels);
+        output_sets.sort_unstable();
+        output_sets.dedup();
+        let subset_minimal = output_sets
+            .iter()
+            .filter(|a| !output_sets.iter().any(|b| *a != b && b.subsumes(a)))
+            .count();
+
+        assert_eq!(minimal_len(None), 6);
+        assert_eq!(minimal_len(Some(all_perms)), 6);
+        assert_eq!(
+            minimal_len(Some(vec![(0..channels).collect()])),
+            subset_minimal
+        );
+        assert!(subset_minimal > 6);
+    }
 }
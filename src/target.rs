@@ -0,0 +1,260 @@
+use std::fmt;
+
+use crate::{
+    output_set::{CVec, OutputSet},
+    value::Value,
+};
+
+/// Condition on the output set of a network that solves the problem being searched for.
+///
+/// Output sets are only considered up to channel permutation and are pruned by subsumption, so a
+/// target has to be invariant under channel permutations and hold for every subset of an output
+/// set that satisfies it. Skipping comparators where one channel implies the other is valid for
+/// all such targets.
+pub trait Target: Sync {
+    fn is_satisfied<V: Value>(&self, output_set: &OutputSet<V>) -> bool;
+
+    /// Whether the target is invariant under [`OutputSet::reflect`].
+    fn is_reflection_symmetric(&self, channels: usize) -> bool;
+}
+
+/// The targets selectable from the command line.
+///
+/// Values of a channel are counted from the largest, i.e. the largest value is the 1st largest.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TargetSpec {
+    /// All channels hold their values in order.
+    Sorting,
+    /// Some `k` channels hold the `k` largest values, in any order.
+    TopK(usize),
+    /// Some channel holds the `k`-th largest value.
+    KthLargest(usize),
+    /// The median, i.e. the `(channels + 1) / 2`-th largest value. For an even number of channels
+    /// this is the larger of the two middle values.
+    Median,
+    /// For every `i` up to `k` some channel holds the `i`-th largest value.
+    Selector(usize),
+}
+
+impl TargetSpec {
+    /// Whether `k` is in `1..=channels` when the target has a `k`.
+    pub fn is_valid(&self, channels: usize) -> bool {
+        match *self {
+            TargetSpec::Sorting | TargetSpec::Median => true,
+            TargetSpec::TopK(k) | TargetSpec::KthLargest(k) | TargetSpec::Selector(k) => {
+                k >= 1 && k <= channels
+            }
+        }
+    }
+}
+
+impl Target for TargetSpec {
+    fn is_satisfied<V: Value>(&self, output_set: &OutputSet<V>) -> bool {
+        let channels = output_set.channels();
+        match *self {
+            TargetSpec::Sorting => thresholds(output_set).len() == channels,
+            TargetSpec::TopK(k) => {
+                let mut union = V::ZERO;
+                let mut intersection = !V::ZERO;
+                for &value in output_set.values() {
+                    let weight = value.count_ones() as usize;
+                    if weight <= k {
+                        union |= value;
+                    }
+                    if weight >= k {
+                        intersection &= value;
+                    }
+                }
+                union & intersection == union
+                    && union.count_ones() as usize <= k
+                    && k <= (intersection & ((V::ONE << channels) - V::ONE)).count_ones() as usize
+            }
+            TargetSpec::KthLargest(k) => thresholds(output_set).contains(&k),
+            TargetSpec::Median => thresholds(output_set).contains(&channels.div_ceil(2)),
+            TargetSpec::Selector(k) => (1..=k).all(|i| thresholds(output_set).contains(&i)),
+        }
+    }
+
+    fn is_reflection_symmetric(&self, channels: usize) -> bool {
+        match *self {
+            TargetSpec::Sorting => true,
+            TargetSpec::TopK(k) => 2 * k == channels,
+            TargetSpec::KthLargest(k) => 2 * k == channels + 1,
+            TargetSpec::Median => channels % 2 == 1,
+            TargetSpec::Selector(k) => k + 1 >= channels,
+        }
+    }
+}
+
+/// Returns every `i` such that some channel holds the `i`-th largest value.
+///
+/// A channel holds the `i`-th largest value when it is set exactly for the values with at least
+/// `i` set channels.
+fn thresholds<V: Value>(output_set: &OutputSet<V>) -> CVec<usize> {
+    let channels = output_set.channels();
+
+    let mut min_weight_set = (0..channels).map(|_| channels + 1).collect::<CVec<_>>();
+    let mut max_weight_clear = (0..channels).map(|_| 0).collect::<CVec<_>>();
+
+    for &value in output_set.values() {
+        let weight = value.count_ones() as usize;
+        for channel in 0..channels {
+            if (value >> channel) & V::ONE != V::ZERO {
+                min_weight_set[channel] = min_weight_set[channel].min(weight);
+            } else {
+                max_weight_clear[channel] = max_weight_clear[channel].max(weight + 1);
+            }
+        }
+    }
+
+    (1..=channels)
+        .filter(|&i| {
+            (0..channels)
+                .any(|channel| max_weight_clear[channel] <= i && i <= min_weight_set[channel])
+        })
+        .collect()
+}
+
+/// Error returned when parsing a target fails.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseTargetError;
+
+impl fmt::Display for ParseTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected `sort`, `median`, `top:<k>`, `kth:<k>` or `select:<k>`"
+        )
+    }
+}
+
+impl std::error::Error for ParseTargetError {}
+
+impl fmt::Display for TargetSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TargetSpec::Sorting => write!(f, "sort"),
+            TargetSpec::TopK(k) => write!(f, "top:{}", k),
+            TargetSpec::KthLargest(k) => write!(f, "kth:{}", k),
+            TargetSpec::Median => write!(f, "median"),
+            TargetSpec::Selector(k) => write!(f, "select:{}", k),
+        }
+    }
+}
+
+impl std::str::FromStr for TargetSpec {
+    type Err = ParseTargetError;
+
+    fn from_str(s: &str) -> Result<Self, ParseTargetError> {
+        match s {
+            "sort" => return Ok(TargetSpec::Sorting),
+            "median" => return Ok(TargetSpec::Median),
+            _ => (),
+        }
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().ok_or(ParseTargetError)?;
+        let k = parts
+            .next()
+            .and_then(|k| k.parse::<usize>().ok())
+            .ok_or(ParseTargetError)?;
+        match name {
+            "top" => Ok(TargetSpec::TopK(k)),
+            "kth" => Ok(TargetSpec::KthLargest(k)),
+            "select" => Ok(TargetSpec::Selector(k)),
+            _ => Err(ParseTargetError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::network::Network;
+
+    /// Checks the targets by trying all ways to pick the output channels.
+    fn brute_force(target: TargetSpec, output_set: &OutputSet) -> bool {
+        let channels = output_set.channels();
+        let weight = |value: u16| value.count_ones() as usize;
+        let holds = |channel: usize, i: usize| {
+            output_set
+                .values()
+                .iter()
+                .all(|&value| ((value >> channel) & 1 != 0) == (weight(value) >= i))
+        };
+        match target {
+            TargetSpec::Sorting => (1..=channels).all(|i| (0..channels).any(|c| holds(c, i))),
+            TargetSpec::TopK(k) => (0..1u16 << channels)
+                .filter(|&subset| weight(subset) == k)
+                .any(|subset| {
+                    output_set
+                        .values()
+                        .iter()
+                        .all(|&value| weight(value & subset) == weight(value).min(k))
+                }),
+            TargetSpec::KthLargest(k) => (0..channels).any(|c| holds(c, k)),
+            TargetSpec::Median => (0..channels).any(|c| holds(c, channels.div_ceil(2))),
+            TargetSpec::Selector(k) => (1..=k).all(|i| (0..channels).any(|c| holds(c, i))),
+        }
+    }
+
+    #[test]
+    fn agrees_with_brute_force() {
+        let channels = 6;
+        let comparators = (0..channels)
+            .flat_map(|j| (0..j).map(move |i| (i, j)))
+            .collect::<Vec<_>>();
+
+        let mut targets = vec![TargetSpec::Sorting, TargetSpec::Median];
+        for k in 1..=channels {
+            targets.push(TargetSpec::TopK(k));
+            targets.push(TargetSpec::KthLargest(k));
+            targets.push(TargetSpec::Selector(k));
+        }
+
+        let mut satisfied = vec![0; targets.len()];
+
+        for step in 0..comparators.len() * 3 {
+            let network = Network::new(
+                channels,
+                (0..step)
+                    .map(|index| comparators[(index * 7) % comparators.len()])
+                    .collect(),
+            );
+            let output_set = network.output_set::<u16>();
+
+            for (target, satisfied) in targets.iter().zip(satisfied.iter_mut()) {
+                let result = target.is_satisfied(&output_set);
+                assert_eq!(result, brute_force(*target, &output_set), "{}", target);
+                *satisfied += result as usize;
+
+                let mut reflected = output_set.reflect();
+                reflected.order_channels_by_weight();
+                if target.is_reflection_symmetric(channels) {
+                    assert_eq!(target.is_satisfied(&reflected), result);
+                }
+            }
+        }
+
+        assert!(satisfied.iter().all(|&count| count > 0));
+        assert!(satisfied.iter().any(|&count| count < comparators.len() * 3));
+    }
+
+    #[test]
+    fn parse_targets() {
+        for &target in [
+            TargetSpec::Sorting,
+            TargetSpec::Median,
+            TargetSpec::TopK(3),
+            TargetSpec::KthLargest(2),
+            TargetSpec::Selector(4),
+        ]
+        .iter()
+        {
+            assert_eq!(target.to_string().parse(), Ok(target));
+        }
+        assert_eq!("top".parse::<TargetSpec>(), Err(ParseTargetError));
+        assert_eq!("top:x".parse::<TargetSpec>(), Err(ParseTargetError));
+        assert_eq!("bottom:3".parse::<TargetSpec>(), Err(ParseTargetError));
+    }
+}
@@ -0,0 +1,411 @@
+use std::fmt;
+
+use crate::output_set::CVec;
+
+/// Largest number of automorphisms of a topology that can be used for subsumption.
+///
+/// Every subsumption check tries each automorphism in turn, so larger groups are refused.
+pub const MAX_AUTOMORPHISMS: usize = 1024;
+
+/// Graph of the channel pairs that may be connected by a comparator.
+///
+/// Unless the topology is complete, the comparators on an edge may put the larger value on either
+/// channel, and the outputs of a network are only required to be in order up to a fixed
+/// relabeling. Channel permutations that map the topology onto itself preserve the set of
+/// networks, so only those can be used for subsumption.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Topology {
+    channels: usize,
+    edges: Vec<(usize, usize)>,
+}
+
+impl Topology {
+    pub fn complete(channels: usize) -> Self {
+        Self::from_edges(
+            channels,
+            (0..channels)
+                .flat_map(|j| (0..j).map(move |i| (i, j)))
+                .collect(),
+        )
+    }
+
+    /// Only adjacent channels are connected.
+    pub fn path(channels: usize) -> Self {
+        Self::from_edges(channels, (1..channels).map(|j| (j - 1, j)).collect())
+    }
+
+    /// Channels form a grid with `rows * cols` channels numbered row by row, connected to their
+    /// horizontal and vertical neighbors.
+    pub fn mesh(rows: usize, cols: usize) -> Self {
+        let mut edges = vec![];
+        for row in 0..rows {
+            for col in 0..cols {
+                let channel = row * cols + col;
+                if col + 1 < cols {
+                    edges.push((channel, channel + 1));
+                }
+                if row + 1 < rows {
+                    edges.push((channel, channel + cols));
+                }
+            }
+        }
+        Self::from_edges(rows * cols, edges)
+    }
+
+    pub fn from_edges(channels: usize, edges: Vec<(usize, usize)>) -> Self {
+        assert!(edges
+            .iter()
+            .all(|&(a, b)| a != b && a < channels && b < channels));
+        let mut edges = edges
+            .into_iter()
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect::<Vec<_>>();
+        edges.sort_unstable();
+        edges.dedup();
+        Self { channels, edges }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn edges(&self) -> &[(usize, usize)] {
+        &self.edges
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.edges.len() == self.channels * self.channels.saturating_sub(1) / 2
+    }
+
+    /// The comparators allowed on the edges, in both orientations.
+    pub fn comparators(&self) -> Vec<(usize, usize)> {
+        self.edges
+            .iter()
+            .flat_map(|&(a, b)| vec![(a, b), (b, a)])
+            .collect()
+    }
+
+    /// Whether reversing the order of the channels maps the topology onto itself.
+    ///
+    /// This is needed to use reflection for subsumption.
+    pub fn is_reversal_symmetric(&self) -> bool {
+        let reversed = Self::from_edges(
+            self.channels,
+            self.edges
+                .iter()
+                .map(|&(a, b)| (self.channels - 1 - a, self.channels - 1 - b))
+                .collect(),
+        );
+        reversed == *self
+    }
+
+    /// Whether every channel can be reached from every other channel along the edges.
+    ///
+    /// Values can never move between the components of a disconnected topology, so no network on
+    /// it sorts.
+    pub fn is_connected(&self) -> bool {
+        fn root(component: &mut [usize], mut channel: usize) -> usize {
+            while component[channel] != channel {
+                component[channel] = component[component[channel]];
+                channel = component[channel];
+            }
+            channel
+        }
+
+        let mut component = (0..self.channels).collect::<Vec<_>>();
+        let mut components = self.channels;
+        for &(a, b) in self.edges.iter() {
+            let (root_a, root_b) = (root(&mut component, a), root(&mut component, b));
+            if root_a != root_b {
+                component[root_a] = root_b;
+                components -= 1;
+            }
+        }
+        components <= 1
+    }
+
+    /// Returns all channel permutations that map the topology onto itself.
+    ///
+    /// Fails once more than [`MAX_AUTOMORPHISMS`] are found.
+    pub fn automorphisms(&self) -> Result<Vec<CVec<usize>>, TopologyError> {
+        fn search(
+            adjacency: &[u32],
+            perm: &mut CVec<usize>,
+            used: u32,
+            automorphisms: &mut Vec<CVec<usize>>,
+        ) -> Result<(), TopologyError> {
+            let channel = perm.len();
+            if channel == adjacency.len() {
+                if automorphisms.len() == MAX_AUTOMORPHISMS {
+                    return Err(TopologyError::TooManyAutomorphisms {
+                        limit: MAX_AUTOMORPHISMS,
+                    });
+                }
+                automorphisms.push(perm.clone());
+                return Ok(());
+            }
+            for image in 0..adjacency.len() {
+                if used & (1 << image) != 0
+                    || adjacency[image].count_ones() != adjacency[channel].count_ones()
+                {
+                    continue;
+                }
+                let consistent = perm.iter().enumerate().all(|(other, &other_image)| {
+                    (adjacency[channel] >> other) & 1 == (adjacency[image] >> other_image) & 1
+                });
+                if consistent {
+                    perm.push(image);
+                    search(adjacency, perm, used | (1 << image), automorphisms)?;
+                    perm.pop();
+                }
+            }
+            Ok(())
+        }
+
+        let mut adjacency = vec![0u32; self.channels];
+        for &(a, b) in self.edges.iter() {
+            adjacency[a] |= 1 << b;
+            adjacency[b] |= 1 << a;
+        }
+
+        let mut automorphisms = vec![];
+        search(&adjacency, &mut CVec::new(), 0, &mut automorphisms)?;
+        Ok(automorphisms)
+    }
+}
+
+/// Specification of a topology, parsed from `complete`, `path`, `mesh:<rows>x<cols>` or
+/// `edges:<a>-<b>,<c>-<d>,...`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TopologySpec {
+    Complete,
+    Path,
+    Mesh(usize, usize),
+    Edges(Vec<(usize, usize)>),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TopologyError {
+    WrongWidth { expected: usize, found: usize },
+    InvalidChannel { channel: usize },
+    TooManyAutomorphisms { limit: usize },
+    Disconnected,
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TopologyError::WrongWidth { expected, found } => {
+                write!(f, "topology has {} channels, expected {}", found, expected)
+            }
+            TopologyError::InvalidChannel { channel } => {
+                write!(f, "channel {} does not exist", channel)
+            }
+            TopologyError::TooManyAutomorphisms { limit } => {
+                write!(f, "topology has more than {} automorphisms", limit)
+            }
+            TopologyError::Disconnected => write!(f, "topology is not connected"),
+        }
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+impl TopologySpec {
+    /// Builds the topology for `channels` channels.
+    ///
+    /// Fails for disconnected topologies, as no network on them sorts.
+    pub fn topology(&self, channels: usize) -> Result<Topology, TopologyError> {
+        let topology = match self {
+            TopologySpec::Complete => Topology::complete(channels),
+            TopologySpec::Path => Topology::path(channels),
+            &TopologySpec::Mesh(rows, cols) => {
+                if rows * cols != channels {
+                    return Err(TopologyError::WrongWidth {
+                        expected: channels,
+                        found: rows * cols,
+                    });
+                }
+                Topology::mesh(rows, cols)
+            }
+            TopologySpec::Edges(edges) => {
+                if let Some(channel) = edges
+                    .iter()
+                    .flat_map(|&(a, b)| vec![a, b])
+                    .find(|&channel| channel >= channels)
+                {
+                    return Err(TopologyError::InvalidChannel { channel });
+                }
+                Topology::from_edges(channels, edges.clone())
+            }
+        };
+        if !topology.is_connected() {
+            return Err(TopologyError::Disconnected);
+        }
+        Ok(topology)
+    }
+}
+
+/// Error returned when parsing a topology specification fails.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseTopologyError;
+
+impl fmt::Display for ParseTopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected `complete`, `path`, `mesh:<rows>x<cols>` or `edges:<a>-<b>,...`"
+        )
+    }
+}
+
+impl std::error::Error for ParseTopologyError {}
+
+impl fmt::Display for TopologySpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TopologySpec::Complete => write!(f, "complete"),
+            TopologySpec::Path => write!(f, "path"),
+            TopologySpec::Mesh(rows, cols) => write!(f, "mesh:{}x{}", rows, cols),
+            TopologySpec::Edges(edges) => {
+                write!(f, "edges:")?;
+                for (index, (a, b)) in edges.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}-{}", a, b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for TopologySpec {
+    type Err = ParseTopologyError;
+
+    fn from_str(s: &str) -> Result<Self, ParseTopologyError> {
+        match s {
+            "complete" => return Ok(TopologySpec::Complete),
+            "path" => return Ok(TopologySpec::Path),
+            _ => (),
+        }
+
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().ok_or(ParseTopologyError)?;
+        let arg = parts.next().ok_or(ParseTopologyError)?;
+
+        let pair = |text: &str, separator: char| {
+            let mut numbers = text.splitn(2, separator).map(|number| number.parse().ok());
+            match (numbers.next(), numbers.next()) {
+                (Some(Some(a)), Some(Some(b))) => Ok((a, b)),
+                _ => Err(ParseTopologyError),
+            }
+        };
+
+        match name {
+            "mesh" => {
+                let (rows, cols) = pair(arg, 'x')?;
+                Ok(TopologySpec::Mesh(rows, cols))
+            }
+            "edges" => {
+                let edges = arg
+                    .split(',')
+                    .map(|edge| pair(edge, '-'))
+                    .collect::<Result<Vec<(usize, usize)>, _>>()?;
+                if edges.iter().any(|&(a, b)| a == b) {
+                    return Err(ParseTopologyError);
+                }
+                Ok(TopologySpec::Edges(edges))
+            }
+            _ => Err(ParseTopologyError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn automorphism_counts() {
+        let count = |topology: &Topology| topology.automorphisms().map(|perms| perms.len());
+
+        assert_eq!(count(&Topology::complete(5)), Ok(120));
+        assert!(Topology::complete(5).is_complete());
+        assert_eq!(count(&Topology::path(6)), Ok(2));
+        assert!(Topology::path(6).is_reversal_symmetric());
+        assert_eq!(count(&Topology::mesh(2, 3)), Ok(4));
+        assert_eq!(count(&Topology::mesh(3, 3)), Ok(8));
+
+        let star = Topology::from_edges(5, vec![(0, 1), (0, 2), (0, 3), (0, 4)]);
+        assert_eq!(count(&star), Ok(24));
+        assert!(!star.is_reversal_symmetric());
+        assert!(!star.is_complete());
+
+        let large_star = Topology::from_edges(12, (1..12).map(|leaf| (0, leaf)).collect());
+        assert_eq!(
+            large_star.automorphisms(),
+            Err(TopologyError::TooManyAutomorphisms {
+                limit: MAX_AUTOMORPHISMS
+            })
+        );
+
+        for topology in [Topology::mesh(2, 3), star].iter() {
+            for perm in topology.automorphisms().unwrap() {
+                let mapped = Topology::from_edges(
+                    topology.channels(),
+                    topology
+                        .edges()
+                        .iter()
+                        .map(|&(a, b)| (perm[a], perm[b]))
+                        .collect(),
+                );
+                assert_eq!(mapped, *topology);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_topologies() {
+        let parse = |text: &str| text.parse::<TopologySpec>();
+
+        assert_eq!(parse("path"), Ok(TopologySpec::Path));
+        assert_eq!(parse("mesh:2x3"), Ok(TopologySpec::Mesh(2, 3)));
+        assert_eq!(
+            parse("edges:0-1,2-1"),
+            Ok(TopologySpec::Edges(vec![(0, 1), (2, 1)]))
+        );
+
+        for spec in ["complete", "path", "mesh:2x3", "edges:0-1,2-1"].iter() {
+            assert_eq!(parse(spec).unwrap().to_string(), *spec);
+        }
+
+        for spec in ["", "ring", "mesh:2", "mesh:2x", "edges:", "edges:1-1"].iter() {
+            assert_eq!(parse(spec), Err(ParseTopologyError));
+        }
+
+        assert_eq!(
+            TopologySpec::Mesh(2, 3).topology(5),
+            Err(TopologyError::WrongWidth {
+                expected: 5,
+                found: 6
+            })
+        );
+        assert_eq!(
+            TopologySpec::Edges(vec![(0, 5)]).topology(5),
+            Err(TopologyError::InvalidChannel { channel: 5 })
+        );
+        assert_eq!(
+            "edges:0-1,2-3".parse::<TopologySpec>().unwrap().topology(4),
+            Err(TopologyError::Disconnected)
+        );
+        assert!(TopologySpec::Edges(vec![(0, 1), (2, 3), (1, 2)])
+            .topology(4)
+            .is_ok());
+        assert_eq!(
+            TopologySpec::Path.topology(3).unwrap().edges(),
+            &[(0, 1), (1, 2)]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fmt,
+    hash::Hash,
+    iter::FromIterator,
+    ops::{
+        Add, AddAssign, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, Deref, DerefMut, Not,
+        Shl, Shr, Sub,
+    },
+};
+
+/// Unsigned integer type used to store the values of an output set.
+///
+/// Bit `i` of a value is the value on channel `i`. The same type is used for the counts stored in
+/// an abstraction, which never exceed the number of values.
+pub trait Value:
+    Copy
+    + Ord
+    + Hash
+    + Default
+    + fmt::Debug
+    + fmt::Binary
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + AddAssign
+    + Sub<Output = Self>
+    + BitAnd<Output = Self>
+    + BitAndAssign
+    + BitOr<Output = Self>
+    + BitOrAssign
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + Shl<usize, Output = Self>
+    + Shr<usize, Output = Self>
+{
+    /// Largest number of channels supported when using this value type.
+    const MAX_CHANNELS: usize;
+
+    const BITS: usize;
+
+    const ZERO: Self;
+
+    const ONE: Self;
+
+    /// Storage for the values of an abstraction.
+    type AbstractionValues: Deref<Target = [Self]>
+        + DerefMut
+        + FromIterator<Self>
+        + Clone
+        + fmt::Debug
+        + Send
+        + Sync;
+
+    /// Scratch storage for up to `2^MAX_CHANNELS` values.
+    type Buffer: Deref<Target = [Self]> + DerefMut + Default + Extend<Self>;
+
+    fn from_usize(value: usize) -> Self;
+
+    fn to_usize(self) -> usize;
+
+    fn count_ones(self) -> u32;
+
+    fn reverse_bits(self) -> Self;
+}
+
+pub type AVec<T> = arrayvec::ArrayVec<[T; 512]>;
+
+type VVec<T> = arrayvec::ArrayVec<[T; 1 << 15]>;
+
+impl Value for u16 {
+    const MAX_CHANNELS: usize = 15;
+    const BITS: usize = 16;
+    const ZERO: Self = 0;
+    const ONE: Self = 1;
+
+    type AbstractionValues = AVec<u16>;
+    type Buffer = VVec<u16>;
+
+    fn from_usize(value: usize) -> Self {
+        value as u16
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn count_ones(self) -> u32 {
+        self.count_ones()
+    }
+
+    fn reverse_bits(self) -> Self {
+        self.reverse_bits()
+    }
+}
+
+impl Value for u32 {
+    const MAX_CHANNELS: usize = 24;
+    const BITS: usize = 32;
+    const ZERO: Self = 0;
+    const ONE: Self = 1;
+
+    type AbstractionValues = Vec<u32>;
+    type Buffer = Vec<u32>;
+
+    fn from_usize(value: usize) -> Self {
+        value as u32
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    fn count_ones(self) -> u32 {
+        self.count_ones()
+    }
+
+    fn reverse_bits(self) -> Self {
+        self.reverse_bits()
+    }
+}